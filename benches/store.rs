@@ -0,0 +1,124 @@
+//! Compares the cost of checks in [`MemoryStore`] and [`ShardedMemoryStore`].
+//!
+//! Run with `cargo bench --bench store`.
+
+use actix_web_ratelimit::config::RateLimitConfig;
+use actix_web_ratelimit::store::{MemoryStore, RateLimitStore, ShardedMemoryStore};
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use std::{
+    hint::black_box,
+    sync::{Arc, Barrier},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Runs `iters` checks of one client, resetting it whenever its window is full so that
+/// every check is allowed and recorded, returning the time taken by the checks.
+fn allowed_checks<S: RateLimitStore>(store: &S, iters: u64, config: &RateLimitConfig) -> Duration {
+    let mut elapsed = Duration::ZERO;
+    let mut left = iters;
+    while left > 0 {
+        store.reset("client").unwrap();
+        let batch = left.min(config.max_requests as u64);
+        let start = Instant::now();
+        for _ in 0..batch {
+            let state = store.check(black_box("client"), 1, config).unwrap();
+            assert!(!black_box(state).limited);
+        }
+        elapsed += start.elapsed();
+        left -= batch;
+    }
+    elapsed
+}
+
+/// Checks of a single client whose window fills up to the limit, on one thread.
+fn hot_key(c: &mut Criterion) {
+    let mut group = c.benchmark_group("hot_key");
+    for max_requests in [100, 10_000, 100_000] {
+        let config = RateLimitConfig::default()
+            .max_requests(max_requests)
+            .window(Duration::from_secs(60));
+
+        let memory = MemoryStore::new();
+        group.bench_with_input(
+            BenchmarkId::new("memory", max_requests),
+            &config,
+            |b, config| b.iter_custom(|iters| allowed_checks(&memory, iters, config)),
+        );
+
+        let sharded = ShardedMemoryStore::new();
+        group.bench_with_input(
+            BenchmarkId::new("sharded", max_requests),
+            &config,
+            |b, config| b.iter_custom(|iters| allowed_checks(&sharded, iters, config)),
+        );
+    }
+    group.finish();
+}
+
+/// Client identifier checked by a thread for its `i`-th check
+type KeyFn = fn(usize, u64) -> String;
+
+/// Runs `iters` checks of `key(thread, i)` spread over `threads` threads on a cleared
+/// store, returning the time taken once all threads started.
+fn contended<S: RateLimitStore + 'static>(
+    store: Arc<S>,
+    threads: usize,
+    iters: u64,
+    config: &RateLimitConfig,
+    key: KeyFn,
+) -> Duration {
+    store.reset_all().unwrap();
+    let barrier = Arc::new(Barrier::new(threads + 1));
+    let handles: Vec<_> = (0..threads)
+        .map(|t| {
+            let (store, barrier, config) = (store.clone(), barrier.clone(), config.clone());
+            let keys: Vec<String> = (0..iters / threads as u64).map(|i| key(t, i)).collect();
+            thread::spawn(move || {
+                barrier.wait();
+                for key in &keys {
+                    let state = store.check(key, 1, &config).unwrap();
+                    assert!(!black_box(state).limited);
+                }
+            })
+        })
+        .collect();
+    barrier.wait();
+    let start = Instant::now();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    start.elapsed()
+}
+
+/// Checks of one client, or of many clients, from several threads at once.
+fn concurrent(c: &mut Criterion) {
+    let threads = thread::available_parallelism()
+        .map_or(4, |n| n.get())
+        .max(2);
+    // Never reached, so that every check is recorded
+    let config = RateLimitConfig::default()
+        .max_requests(usize::MAX)
+        .window(Duration::from_secs(60));
+    let cases: [(&str, KeyFn); 2] = [
+        ("same_key", |_, _| "client".to_string()),
+        ("distinct_keys", |t, i| format!("client-{}-{}", t, i % 1024)),
+    ];
+
+    let mut group = c.benchmark_group(format!("concurrent_{}_threads", threads));
+    for (name, key) in cases {
+        let memory = Arc::new(MemoryStore::new());
+        group.bench_function(BenchmarkId::new("memory", name), |b| {
+            b.iter_custom(|iters| contended(memory.clone(), threads, iters, &config, key))
+        });
+
+        let sharded = Arc::new(ShardedMemoryStore::new());
+        group.bench_function(BenchmarkId::new("sharded", name), |b| {
+            b.iter_custom(|iters| contended(sharded.clone(), threads, iters, &config, key))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, hot_key, concurrent);
+criterion_main!(benches);
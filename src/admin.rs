@@ -0,0 +1,357 @@
+//! HTTP endpoints for inspecting and managing rate limited clients (requires `admin` feature).
+//!
+//! [`scope`] builds an actix [`Scope`] exposing the following routes, relative to the
+//! path it is mounted at:
+//!
+//! | Method   | Route              | Description                                           |
+//! |----------|--------------------|-------------------------------------------------------|
+//! | `GET`    | `/keys/{key}`      | Current state of a client as JSON                     |
+//! | `DELETE` | `/keys/{key}`      | Clears the requests recorded for a client             |
+//! | `PUT`    | `/keys/{key}/ban`  | Bans a client, for `?secs=N` seconds if given         |
+//! | `DELETE` | `/keys/{key}/ban`  | Lifts the ban of a client                             |
+//! | `GET`    | `/top`             | Clients using the most units, `?limit=N` (default 10) |
+//!
+//! The state of a client looks like
+//! `{"key":"1.2.3.4","used":3,"remaining":7,"limit":10,"reset_after_ms":9500,"banned":false,"routes":[],"global":null}`:
+//! the top-level usage is the one of the default per-client limit, `routes` holds the
+//! usage of the client under each [`crate::config::RateLimitConfig::route_limit`], e.g.
+//! `{"path":"/export","used":1,"remaining":4,"limit":5,"reset_after_ms":30000}`, and
+//! `global` the usage shared by all clients if
+//! [`crate::config::RateLimitConfig::global_limit`] is set. Clearing a client resets it
+//! under the default and route limits, leaving the global one alone. The top consumers
+//! look like `[{"key":"1.2.3.4","used":3}]`.
+//!
+//! Operations not supported by the store answer `501 Not Implemented`, calls skipped by
+//! an open [`crate::store::CircuitBreaker`] `503 Service Unavailable`, other store errors
+//! `500 Internal Server Error`.
+//!
+//! Bans are reported to the observers of the configuration, see
+//! [`crate::observer::RateLimitObserver::on_ban_applied`].
+//!
+//! The scope performs no authentication: guard it or wrap it with your own middleware
+//! before exposing it.
+use actix_web::{HttpResponse, Scope, web};
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    ConfigHandle,
+    config::RateLimitConfig,
+    store::{RateLimitStore, StoreError, StoreResult},
+};
+
+/// Number of clients listed by `/top` when no limit is given
+const DEFAULT_TOP_LIMIT: usize = 10;
+
+/// Store and configuration the endpoints operate on.
+struct Admin {
+    store: Arc<dyn RateLimitStore>,
+    handle: ConfigHandle,
+}
+
+#[derive(Serialize)]
+struct Usage {
+    used: usize,
+    remaining: usize,
+    limit: usize,
+    reset_after_ms: u64,
+}
+
+#[derive(Serialize)]
+struct RouteUsage {
+    path: String,
+    #[serde(flatten)]
+    usage: Usage,
+}
+
+#[derive(Serialize)]
+struct KeyState {
+    key: String,
+    #[serde(flatten)]
+    usage: Usage,
+    banned: bool,
+    routes: Vec<RouteUsage>,
+    global: Option<Usage>,
+}
+
+#[derive(Serialize)]
+struct Consumer {
+    key: String,
+    used: usize,
+}
+
+#[derive(Deserialize)]
+struct BanQuery {
+    secs: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct TopQuery {
+    limit: Option<usize>,
+}
+
+/// Builds a [`Scope`] mounted at `path` exposing the admin endpoints.
+///
+/// `handle` is the configuration of the [`crate::RateLimit`] middleware sharing the
+/// store; the limit and window in effect when a request arrives are used to compute the
+/// state of clients, so updates made through the handle apply right away.
+///
+/// # Example
+///
+/// ```rust, no_run
+/// # use actix_web::{App, HttpServer, Responder, web};
+/// # use actix_web_ratelimit::{ConfigHandle, RateLimit, admin, config::RateLimitConfig, store::MemoryStore};
+/// # use std::sync::Arc;
+/// #
+/// # async fn index() -> impl Responder {
+/// #     "Hello world!"
+/// # }
+/// #
+/// # #[actix_web::main]
+/// # async fn main() -> std::io::Result<()> {
+/// let config = RateLimitConfig::default()
+///     .max_requests(3)
+///     .window_secs(10)
+///     .enforce_bans(true);
+/// let handle = ConfigHandle::new(config);
+/// let store = Arc::new(MemoryStore::new());
+///
+/// HttpServer::new(move || {
+///     App::new()
+///         // Only reachable from the internal network, e.g. behind a guard
+///         .service(admin::scope("/admin/ratelimit", handle.clone(), store.clone()))
+///         .service(
+///             web::scope("")
+///                 .wrap(RateLimit::with_handle(handle.clone(), store.clone()))
+///                 .route("/", web::get().to(index)),
+///         )
+/// })
+/// .bind(("127.0.0.1", 8080))?
+/// .run()
+/// .await
+/// # }
+/// ```
+pub fn scope<S>(path: &str, handle: ConfigHandle, store: S) -> Scope
+where
+    S: RateLimitStore + 'static,
+{
+    let admin = web::Data::new(Admin {
+        store: Arc::new(store),
+        handle,
+    });
+    web::scope(path)
+        .app_data(admin)
+        .route("/keys/{key}", web::get().to(get_key))
+        .route("/keys/{key}", web::delete().to(reset_key))
+        .route("/keys/{key}/ban", web::put().to(ban_key))
+        .route("/keys/{key}/ban", web::delete().to(unban_key))
+        .route("/top", web::get().to(top_keys))
+}
+
+/// Maps a store error to an HTTP response.
+fn store_error(err: StoreError) -> HttpResponse {
+    match err {
+        StoreError::Unsupported(_) => HttpResponse::NotImplemented().body(err.to_string()),
+        StoreError::CircuitOpen => HttpResponse::ServiceUnavailable().body(err.to_string()),
+        _ => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+/// Returns the usage of the store key `key` under `config`.
+fn usage(store: &dyn RateLimitStore, key: &str, config: &RateLimitConfig) -> StoreResult<Usage> {
+    let state = store.peek(key, 0, config)?;
+    Ok(Usage {
+        used: state.used,
+        remaining: state.remaining,
+        limit: config.max_requests,
+        reset_after_ms: u64::try_from(state.reset_after.as_millis()).unwrap_or(u64::MAX),
+    })
+}
+
+/// Returns the state of the client `key` under every limit of `config`.
+fn key_state(
+    store: &dyn RateLimitStore,
+    key: String,
+    config: &RateLimitConfig,
+) -> StoreResult<KeyState> {
+    let routes = config
+        .routes
+        .iter()
+        .map(|route| {
+            Ok(RouteUsage {
+                path: route.path.clone(),
+                usage: usage(store, &route.key(&key), &route.config)?,
+            })
+        })
+        .collect::<StoreResult<_>>()?;
+    let global = match &config.global {
+        Some(global) => Some(usage(store, &global.key, &global.config)?),
+        None => None,
+    };
+    Ok(KeyState {
+        usage: usage(store, &key, config)?,
+        banned: store.is_banned(&key)?,
+        routes,
+        global,
+        key,
+    })
+}
+
+async fn get_key(admin: web::Data<Admin>, key: web::Path<String>) -> HttpResponse {
+    let config = admin.handle.config();
+    match key_state(admin.store.as_ref(), key.into_inner(), &config) {
+        Ok(state) => HttpResponse::Ok().json(state),
+        Err(err) => store_error(err),
+    }
+}
+
+async fn reset_key(admin: web::Data<Admin>, key: web::Path<String>) -> HttpResponse {
+    let config = admin.handle.config();
+    let reset = config
+        .routes
+        .iter()
+        .try_for_each(|route| admin.store.reset(&route.key(&key)))
+        .and_then(|()| admin.store.reset(&key));
+    match reset {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => store_error(err),
+    }
+}
+
+async fn ban_key(
+    admin: web::Data<Admin>,
+    key: web::Path<String>,
+    query: web::Query<BanQuery>,
+) -> HttpResponse {
+    let duration = query.secs.map(Duration::from_secs);
+    match admin.store.ban(&key, duration) {
+        Ok(()) => {
+            let active = admin.handle.load();
+            if let Some(dispatcher) = &active.dispatcher {
+                dispatcher.ban_applied(&active.config.policy, &key, duration);
+            }
+            HttpResponse::NoContent().finish()
+        }
+        Err(err) => store_error(err),
+    }
+}
+
+async fn unban_key(admin: web::Data<Admin>, key: web::Path<String>) -> HttpResponse {
+    match admin.store.unban(&key) {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => store_error(err),
+    }
+}
+
+async fn top_keys(admin: web::Data<Admin>, query: web::Query<TopQuery>) -> HttpResponse {
+    let limit = query.limit.unwrap_or(DEFAULT_TOP_LIMIT);
+    match admin.store.top_keys(limit, &admin.handle.config()) {
+        Ok(top) => HttpResponse::Ok().json(
+            top.into_iter()
+                .map(|(key, used)| Consumer { key, used })
+                .collect::<Vec<_>>(),
+        ),
+        Err(err) => store_error(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        observer::{EventInfo, RateLimitObserver},
+        store::MemoryStore,
+    };
+    use actix_web::{App, http::StatusCode, test};
+    use std::sync::{Mutex, mpsc};
+
+    struct Bans(Mutex<mpsc::Sender<(EventInfo, Option<Duration>)>>);
+
+    impl RateLimitObserver for Bans {
+        fn on_ban_applied(&self, info: &EventInfo, duration: Option<Duration>) {
+            let _ = self.0.lock().unwrap().send((info.clone(), duration));
+        }
+    }
+
+    #[actix_web::test]
+    async fn bans_are_reported_to_observers() {
+        let (sender, receiver) = mpsc::channel();
+        let config = RateLimitConfig::default()
+            .policy("api")
+            .observer(Bans(Mutex::new(sender)));
+        let store = Arc::new(MemoryStore::new());
+        let app = test::init_service(App::new().service(scope(
+            "/admin",
+            ConfigHandle::new(config),
+            store.clone(),
+        )))
+        .await;
+
+        for uri in ["/admin/keys/1.2.3.4/ban?secs=60", "/admin/keys/5.6.7.8/ban"] {
+            let response =
+                test::call_service(&app, test::TestRequest::put().uri(uri).to_request()).await;
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        }
+        assert!(store.is_banned("1.2.3.4").unwrap());
+
+        let timeout = Duration::from_secs(5);
+        let (info, duration) = receiver.recv_timeout(timeout).unwrap();
+        assert_eq!(
+            (info.policy.as_str(), info.key.as_str()),
+            ("api", "1.2.3.4")
+        );
+        assert_eq!(duration, Some(Duration::from_secs(60)));
+        let (info, duration) = receiver.recv_timeout(timeout).unwrap();
+        assert_eq!(info.key, "5.6.7.8");
+        assert_eq!(duration, None);
+    }
+
+    #[actix_web::test]
+    async fn keys_report_every_limit() {
+        let config = RateLimitConfig::default()
+            .max_requests(10)
+            .route_limit("/export", 2, Duration::from_secs(60))
+            .global_limit("all", 100, Duration::from_secs(60));
+        let store = Arc::new(MemoryStore::new());
+        store.check("1.2.3.4", 3, &config).unwrap();
+        store
+            .check("/export:1.2.3.4", 1, &config.routes[0].config)
+            .unwrap();
+        store
+            .check("all", 4, &config.global.as_ref().unwrap().config)
+            .unwrap();
+        let app = test::init_service(App::new().service(scope(
+            "/admin",
+            ConfigHandle::new(config),
+            store.clone(),
+        )))
+        .await;
+
+        let request = || {
+            test::TestRequest::get()
+                .uri("/admin/keys/1.2.3.4")
+                .to_request()
+        };
+        let state: serde_json::Value = test::call_and_read_body_json(&app, request()).await;
+        assert_eq!(
+            (state["used"].as_u64(), state["limit"].as_u64()),
+            (Some(3), Some(10))
+        );
+        assert_eq!(state["routes"][0]["path"], "/export");
+        assert_eq!(state["routes"][0]["used"], 1);
+        assert_eq!(state["routes"][0]["remaining"], 1);
+        assert_eq!(state["global"]["used"], 4);
+
+        let reset = test::TestRequest::delete()
+            .uri("/admin/keys/1.2.3.4")
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, reset).await.status(),
+            StatusCode::NO_CONTENT
+        );
+        let state: serde_json::Value = test::call_and_read_body_json(&app, request()).await;
+        assert_eq!(state["used"], 0);
+        assert_eq!(state["routes"][0]["used"], 0);
+        assert_eq!(state["global"]["used"], 4);
+    }
+}
@@ -0,0 +1,228 @@
+use actix_service::{Service, Transform};
+use actix_web::{
+    Error,
+    body::{BodySize, BoxBody, EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    web::Bytes,
+};
+use futures_util::future::{LocalBoxFuture, Ready, ok};
+use log::error;
+use std::{
+    pin::Pin,
+    sync::{
+        Arc, LazyLock,
+        mpsc::{SyncSender, TrySendError, sync_channel},
+    },
+    task::{Context, Poll},
+};
+
+use crate::{config::ConcurrencyConfig, store::ConcurrencyStore};
+
+/// Maximum number of permits waiting for the release thread before they are released
+/// on the worker
+const RELEASE_QUEUE_CAPACITY: usize = 1024;
+
+/// Thread releasing the permits of remote stores, `None` if it could not be started
+static RELEASES: LazyLock<Option<SyncSender<Permit>>> = LazyLock::new(|| {
+    let (sender, receiver) = sync_channel::<Permit>(RELEASE_QUEUE_CAPACITY);
+    let spawned = std::thread::Builder::new()
+        .name("ratelimit-release".to_string())
+        .spawn(move || receiver.into_iter().for_each(Permit::release));
+    match spawned {
+        Ok(_) => Some(sender),
+        Err(err) => {
+            error!("Failed to start permit release thread: {}", err);
+            None
+        }
+    }
+});
+
+/// Middleware capping the number of requests each client may have in flight.
+///
+/// A permit is acquired from the [`ConcurrencyStore`] before the inner service is
+/// called and released once the response body has been sent, or when the request is
+/// dropped. Permits of remote stores, see [`ConcurrencyStore::remote`], are released
+/// from a background thread so that workers never wait on the network for it.
+///
+/// # Example
+///
+/// ```rust, no_run
+/// # use actix_web::{App, HttpServer, Responder, web};
+/// # use actix_web_ratelimit::{ConcurrencyLimit, config::ConcurrencyConfig, store::MemoryStore};
+/// # use std::sync::Arc;
+/// #
+/// # async fn report() -> impl Responder {
+/// #     "report"
+/// # }
+/// #
+/// # #[actix_web::main]
+/// # async fn main() -> std::io::Result<()> {
+/// let config = ConcurrencyConfig::default().max_concurrent(3);
+/// let store = Arc::new(MemoryStore::new());
+///
+/// HttpServer::new(move || {
+///     App::new()
+///         .wrap(ConcurrencyLimit::new(config.clone(), store.clone()))
+///         .route("/report", web::get().to(report))
+/// })
+/// .bind(("127.0.0.1", 8080))?
+/// .run()
+/// .await
+/// # }
+/// ```
+pub struct ConcurrencyLimit<S>
+where
+    S: ConcurrencyStore,
+{
+    store: Arc<S>,
+    config: Arc<ConcurrencyConfig>,
+}
+
+impl<S> ConcurrencyLimit<S>
+where
+    S: ConcurrencyStore,
+{
+    pub fn new(config: ConcurrencyConfig, store: S) -> Self {
+        Self {
+            store: Arc::new(store),
+            config: Arc::new(config),
+        }
+    }
+}
+
+impl<S, B, ST> Transform<S, ServiceRequest> for ConcurrencyLimit<ST>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+    ST: ConcurrencyStore + 'static,
+{
+    type Response = ServiceResponse<EitherBody<PermitBody>>;
+    type Error = Error;
+    type Transform = ConcurrencyLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ConcurrencyLimitMiddleware {
+            service,
+            store: self.store.clone(),
+            config: self.config.clone(),
+        })
+    }
+}
+
+pub struct ConcurrencyLimitMiddleware<S> {
+    service: S,
+    store: Arc<dyn ConcurrencyStore>,
+    config: Arc<ConcurrencyConfig>,
+}
+
+/// In-flight permit of a request.
+struct Permit {
+    store: Arc<dyn ConcurrencyStore>,
+    key: String,
+    lease: u64,
+}
+
+impl Permit {
+    fn release(self) {
+        if let Err(err) = self.store.release(&self.key, self.lease) {
+            error!("Failed to release permit for key({}): {}", self.key, err);
+        }
+    }
+}
+
+/// Releases a permit when dropped, on the release thread if its store is remote.
+struct PermitGuard(Option<Permit>);
+
+impl Drop for PermitGuard {
+    fn drop(&mut self) {
+        let Some(permit) = self.0.take() else {
+            return;
+        };
+        let permit = match &*RELEASES {
+            Some(releases) if permit.store.remote() => match releases.try_send(permit) {
+                Ok(()) => return,
+                Err(TrySendError::Full(permit) | TrySendError::Disconnected(permit)) => permit,
+            },
+            _ => permit,
+        };
+        permit.release();
+    }
+}
+
+/// Response body of [`ConcurrencyLimit`], holding the permit of its request until the
+/// body has been sent or is dropped.
+pub struct PermitBody {
+    body: BoxBody,
+    permit: Option<PermitGuard>,
+}
+
+impl MessageBody for PermitBody {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        let next = Pin::new(&mut this.body).poll_next(cx);
+        if let Poll::Ready(None | Some(Err(_))) = next {
+            this.permit = None;
+        }
+        next
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for ConcurrencyLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<PermitBody>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let key = (self.config.get_id)(&req);
+
+        let permit = match self.store.acquire(&key, &self.config) {
+            Ok(Some(lease)) => Some(PermitGuard(Some(Permit {
+                store: self.store.clone(),
+                key,
+                lease,
+            }))),
+            Ok(None) => {
+                let res = (self.config.on_exceed)(&key, &self.config, &req);
+                let res = req.into_response(res).map_into_right_body();
+                return Box::pin(async { Ok(res) });
+            }
+            // Fallback: allow request when the store fails (graceful degradation)
+            Err(err) => {
+                error!("Concurrency store error for key({}): {}", key, err);
+                None
+            }
+        };
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await?;
+            Ok(res
+                .map_body(|_, body| PermitBody {
+                    body: body.boxed(),
+                    permit,
+                })
+                .map_into_left_body())
+        })
+    }
+}
@@ -1,5 +1,14 @@
-use actix_web::{HttpResponse, dev::ServiceRequest};
-use std::time::Duration;
+use actix_web::{
+    HttpResponse,
+    dev::ServiceRequest,
+    http::{StatusCode, header::HeaderName},
+};
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use crate::{
+    observer::RateLimitObserver,
+    rate::{Rate, RateParseError},
+};
 
 /// Configuration for rate limiting middleware.
 ///
@@ -38,6 +47,10 @@ use std::time::Duration;
 /// ```
 #[derive(Clone)]
 pub struct RateLimitConfig {
+    /// Name of the policy, used to label logs and metrics
+    pub policy: String,
+    /// Whether client identifiers are hashed before being attached to traces
+    pub hash_keys: bool,
     /// Maximum number of requests allowed within the time window
     pub max_requests: usize,
     /// Duration of the sliding time window
@@ -45,9 +58,85 @@ pub struct RateLimitConfig {
     /// Function to extract client identifier from the request.
     /// Typically extracts IP address, but can be customized for API keys, user IDs, etc.
     pub get_id: fn(req: &ServiceRequest) -> String,
+    /// Function to compute how many units of the quota a request consumes.
+    /// `max_requests` and the reported remaining quota are expressed in these units.
+    pub get_cost: fn(req: &ServiceRequest) -> usize,
     /// Function called when rate limit is exceeded.
     /// Receives the client ID, configuration, and request, returns the HTTP response.
     pub on_exceed: fn(id: &String, config: &RateLimitConfig, req: &ServiceRequest) -> HttpResponse,
+    /// Function called when a banned client sends a request.
+    /// Receives the client ID, configuration, and request, returns the HTTP response.
+    pub on_banned: fn(id: &String, config: &RateLimitConfig, req: &ServiceRequest) -> HttpResponse,
+    /// Whether the store is asked if the client is banned before every check
+    pub enforce_bans: bool,
+    /// If set, requests are only counted after the response, when this returns `true`
+    /// for its status. The limit is still checked before the inner service runs.
+    pub count_if: Option<fn(status: StatusCode) -> bool>,
+    /// If set, requests counted before the inner service runs are refunded when this
+    /// returns `true` for the response status.
+    pub refund_if: Option<fn(status: StatusCode) -> bool>,
+    /// If set, limited requests wait up to this long for the quota instead of being
+    /// rejected right away.
+    pub max_delay: Option<Duration>,
+    /// Maximum number of requests per client waiting at the same time in delay mode.
+    pub max_queue: usize,
+    /// Optional limit shared by all clients, checked together with the per-client limit.
+    pub global: Option<Arc<GlobalLimit>>,
+    /// Client identifiers never rate limited
+    pub allowlist: HashSet<String>,
+    /// Limits replacing the per-client limit on matching paths, the first match wins
+    pub routes: Vec<RouteLimit>,
+    /// Observers notified of limiter events on a background thread.
+    pub observers: Vec<Arc<dyn RateLimitObserver>>,
+    /// Whether limits are only recorded and reported, never enforced
+    pub shadow: bool,
+    /// Response header set to `would-limit` on requests let through in shadow mode
+    pub shadow_header: Option<HeaderName>,
+    /// Whether requests are let through when the store fails or times out, rather than
+    /// rejected with HTTP 503
+    pub fail_open: bool,
+}
+
+/// Limit shared by all clients under a fixed store key.
+///
+/// Set with [`RateLimitConfig::global_limit`]. Every request is counted both against
+/// its client's key and against this key, and is only allowed if it fits within both.
+#[derive(Clone)]
+pub struct GlobalLimit {
+    /// Store key shared by all clients
+    pub key: String,
+    /// Limit of the shared key; only `max_requests` and `window_secs` are used
+    pub config: RateLimitConfig,
+}
+
+/// Per-client limit applying to requests under a path prefix.
+///
+/// The prefix is matched by whole path segments: `/login` matches `/login` and
+/// `/login/otp` but not `/loginfoo`.
+///
+/// Set with [`RateLimitConfig::route_limit`]. Matching requests are counted under the
+/// store key `{path}:{client_id}` against this limit instead of the default one, so
+/// every route gets its own budget.
+#[derive(Clone)]
+pub struct RouteLimit {
+    /// Path prefix the limit applies to
+    pub path: String,
+    /// Limit of the route; only `max_requests` and `window_secs` are used
+    pub config: RateLimitConfig,
+}
+
+impl RouteLimit {
+    /// Store key counting the requests of a client on this route.
+    pub(crate) fn key(&self, id: &str) -> String {
+        format!("{}:{}", self.path, id)
+    }
+
+    /// Whether `path` equals the prefix of the route or continues it with a new segment.
+    pub(crate) fn matches(&self, path: &str) -> bool {
+        path.strip_prefix(self.path.as_str()).is_some_and(|rest| {
+            rest.is_empty() || rest.starts_with('/') || self.path.ends_with('/')
+        })
+    }
 }
 
 impl Default for RateLimitConfig {
@@ -55,10 +144,26 @@ impl Default for RateLimitConfig {
     ///
     /// # Default Values
     ///
+    /// - `policy`: `"default"`
+    /// - `hash_keys`: `false`, client identifiers appear in traces as is
     /// - `max_requests`: 10 requests
     /// - `window_secs`: 100 seconds
     /// - `get_id`: Extracts real IP address from connection info
+    /// - `get_cost`: Every request costs 1 unit
     /// - `on_exceed`: Returns HTTP 429 "Too Many Requests" with plain text body
+    /// - `on_banned`: Returns HTTP 403 "Forbidden" with plain text body
+    /// - `enforce_bans`: `false`, bans are not looked up
+    /// - `count_if`: `None`, every request is counted before the inner service runs
+    /// - `refund_if`: `None`, counted requests are never refunded
+    /// - `max_delay`: `None`, limited requests are rejected immediately
+    /// - `max_queue`: 0 waiting requests per client
+    /// - `global`: `None`, only the per-client limit applies
+    /// - `allowlist`: empty
+    /// - `routes`: none, the same limit applies to all paths
+    /// - `observers`: none
+    /// - `shadow`: `false`, limits are enforced
+    /// - `shadow_header`: `None`
+    /// - `fail_open`: `true`, requests are let through when the store fails
     ///
     /// # Example
     ///
@@ -70,6 +175,8 @@ impl Default for RateLimitConfig {
     /// ```
     fn default() -> Self {
         Self {
+            policy: "default".to_string(),
+            hash_keys: false,
             max_requests: 10,
             window_secs: Duration::from_secs(100),
             get_id: |req| {
@@ -78,15 +185,62 @@ impl Default for RateLimitConfig {
                     .unwrap_or("-")
                     .to_string()
             },
+            get_cost: |_req| 1,
             on_exceed: |_id, _config, _req| {
-                HttpResponse::TooManyRequests()
-                    .body("Too many requests")
+                HttpResponse::TooManyRequests().body("Too many requests")
             },
+            on_banned: |_id, _config, _req| HttpResponse::Forbidden().body("Banned"),
+            enforce_bans: false,
+            count_if: None,
+            refund_if: None,
+            max_delay: None,
+            max_queue: 0,
+            global: None,
+            allowlist: HashSet::new(),
+            routes: Vec::new(),
+            observers: Vec::new(),
+            shadow: false,
+            shadow_header: None,
+            fail_open: true,
         }
     }
 }
 
 impl RateLimitConfig {
+    /// Sets the name of the policy.
+    ///
+    /// The name labels the metrics and logs of the middleware, which is useful to tell
+    /// apart several [`crate::RateLimit`] instances, e.g. one per scope.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    ///
+    /// let config = RateLimitConfig::default().policy("login").max_requests(5);
+    /// ```
+    pub fn policy(mut self, name: &str) -> Self {
+        self.policy = name.to_string();
+        Self { ..self }
+    }
+
+    /// Sets whether client identifiers are hashed before being attached to traces.
+    ///
+    /// Useful when identifiers are personal data such as IP addresses or user IDs.
+    /// The hash is stable for a given build but is not a cryptographic digest.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    ///
+    /// let config = RateLimitConfig::default().hash_keys(true);
+    /// ```
+    pub fn hash_keys(mut self, value: bool) -> Self {
+        self.hash_keys = value;
+        Self { ..self }
+    }
+
     /// Sets the maximum number of requests allowed within the time window.
     ///
     /// # Arguments
@@ -126,6 +280,92 @@ impl RateLimitConfig {
         Self { ..self }
     }
 
+    /// Sets the time window duration for the sliding window algorithm with sub-second
+    /// precision.
+    ///
+    /// `MemoryStore` keeps the full precision of [`Duration`], `RedisStore` rounds the
+    /// window down to whole milliseconds.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - Time window duration
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    /// use actix_web_ratelimit::store::{MemoryStore, RateLimitStore};
+    /// use std::time::Duration;
+    ///
+    /// // Allow 5 requests per 50 milliseconds
+    /// let config = RateLimitConfig::default()
+    ///     .max_requests(5)
+    ///     .window(Duration::from_millis(50));
+    ///
+    /// let store = MemoryStore::new();
+    /// store.check("client", 5, &config).unwrap();
+    /// assert!(store.check("client", 1, &config).unwrap().limited);
+    ///
+    /// std::thread::sleep(Duration::from_millis(60));
+    /// assert!(!store.check("client", 1, &config).unwrap().limited);
+    /// ```
+    pub fn window(mut self, value: Duration) -> Self {
+        self.window_secs = value;
+        Self { ..self }
+    }
+
+    /// Creates a default configuration limited by a rate string, see [`crate::rate`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RateParseError`] describing what is wrong with the string.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    /// use std::time::Duration;
+    ///
+    /// let config = RateLimitConfig::from_rate("100 per minute")?;
+    /// assert_eq!(config.max_requests, 100);
+    /// assert_eq!(config.window_secs, Duration::from_secs(60));
+    ///
+    /// assert!(RateLimitConfig::from_rate("100 per").is_err());
+    /// # Ok::<(), actix_web_ratelimit::rate::RateParseError>(())
+    /// ```
+    pub fn from_rate(rate: &str) -> Result<Self, RateParseError> {
+        Ok(Self::default().rate(rate.parse()?))
+    }
+
+    /// Sets the limit and window from a [`Rate`].
+    ///
+    /// A burst enables delay mode: up to `burst` requests per client wait for the quota,
+    /// each for at most the time `burst` requests take at the given rate.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    /// use std::time::Duration;
+    ///
+    /// let config = RateLimitConfig::default().rate("5r/s burst=10".parse()?);
+    /// assert_eq!(config.max_queue, 10);
+    /// assert_eq!(config.max_delay, Some(Duration::from_secs(2)));
+    /// # Ok::<(), actix_web_ratelimit::rate::RateParseError>(())
+    /// ```
+    pub fn rate(mut self, rate: Rate) -> Self {
+        self.max_requests = rate.max_requests;
+        self.window_secs = rate.window;
+        if let Some(burst) = rate.burst {
+            // A hand-built rate may hold no requests, count it as one to bound the wait
+            let per_request = rate.window.as_secs_f64() / rate.max_requests.max(1) as f64;
+            let max_delay =
+                Duration::try_from_secs_f64(per_request * burst as f64).unwrap_or(Duration::MAX);
+            self = self.delay(max_delay, burst);
+        }
+        Self { ..self }
+    }
+
     /// Sets a custom function to extract client identifier from requests.
     ///
     /// By default, the middleware uses the client's IP address as identifier.
@@ -166,6 +406,30 @@ impl RateLimitConfig {
         Self { ..self }
     }
 
+    /// Sets a custom function to compute the cost of a request.
+    ///
+    /// By default, every request costs 1 unit. Expensive endpoints can consume more of
+    /// the quota, in which case `max_requests` is the number of units allowed per window.
+    ///
+    /// # Arguments
+    ///
+    /// * `fn_cost` - Function that takes a `ServiceRequest` and returns its cost in units
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    ///
+    /// // Exports are ten times as expensive as other requests
+    /// let config = RateLimitConfig::default()
+    ///     .max_requests(100)
+    ///     .cost(|req| if req.path().starts_with("/export") { 10 } else { 1 });
+    /// ```
+    pub fn cost(mut self, fn_cost: fn(req: &ServiceRequest) -> usize) -> Self {
+        self.get_cost = fn_cost;
+        Self { ..self }
+    }
+
     /// Sets a custom function to handle rate limit exceeded scenarios.
     ///
     /// By default, returns HTTP 429 with "Too many requests" message.
@@ -209,4 +473,426 @@ impl RateLimitConfig {
         self.on_exceed = fn_exceed;
         Self { ..self }
     }
+
+    /// Sets a custom response for clients banned with [`crate::store::RateLimitStore::ban`],
+    /// see [`RateLimitConfig::enforce_bans`].
+    ///
+    /// # Arguments
+    ///
+    /// * `fn_banned` - Function that generates the HTTP response for banned clients
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web::HttpResponse;
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    ///
+    /// let config = RateLimitConfig::default()
+    ///     .banned(|_id, _config, _req| HttpResponse::Forbidden().body("Contact support"));
+    /// ```
+    pub fn banned(
+        mut self,
+        fn_banned: fn(id: &String, config: &RateLimitConfig, req: &ServiceRequest) -> HttpResponse,
+    ) -> Self {
+        self.on_banned = fn_banned;
+        Self { ..self }
+    }
+
+    /// Sets whether clients banned with [`crate::store::RateLimitStore::ban`] are rejected
+    /// with `on_banned`.
+    ///
+    /// Looking up bans costs one more store call per request, e.g. a Redis round trip, so
+    /// it is disabled by default. A failed lookup is handled like a failed check, see
+    /// [`RateLimitConfig::fail_open`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    ///
+    /// let config = RateLimitConfig::default().enforce_bans(true);
+    /// assert!(config.enforce_bans);
+    /// ```
+    pub fn enforce_bans(mut self, value: bool) -> Self {
+        self.enforce_bans = value;
+        Self { ..self }
+    }
+
+    /// Counts requests only when the response status matches, instead of before the
+    /// inner service runs.
+    ///
+    /// The limit is still checked up front without recording anything, and the request
+    /// is recorded once the response is known. This is useful for login endpoints where
+    /// only failed attempts should consume the quota. An error returned by the inner
+    /// service is matched by the status of the response it renders to. Takes precedence
+    /// over [`RateLimitConfig::refund_if`].
+    ///
+    /// # Arguments
+    ///
+    /// * `fn_count` - Function that takes the response status and returns whether to count it
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web::http::StatusCode;
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    ///
+    /// // Allow 5 failed login attempts per 5 minutes
+    /// let config = RateLimitConfig::default()
+    ///     .max_requests(5)
+    ///     .window_secs(300)
+    ///     .count_if(|status| {
+    ///         status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN
+    ///     });
+    /// ```
+    pub fn count_if(mut self, fn_count: fn(status: StatusCode) -> bool) -> Self {
+        self.count_if = Some(fn_count);
+        Self { ..self }
+    }
+
+    /// Refunds requests whose response status matches.
+    ///
+    /// Requests are counted before the inner service runs as usual, and their cost is
+    /// given back to the client once the response is known. An error returned by the
+    /// inner service is matched by the status of the response it renders to. Responses
+    /// completing after the window the request was counted in has ended are not
+    /// refunded, as the units would be taken from the next window.
+    ///
+    /// # Arguments
+    ///
+    /// * `fn_refund` - Function that takes the response status and returns whether to refund it
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    ///
+    /// // Don't charge clients for upstream failures
+    /// let config = RateLimitConfig::default().refund_if(|status| status.is_server_error());
+    /// ```
+    pub fn refund_if(mut self, fn_refund: fn(status: StatusCode) -> bool) -> Self {
+        self.refund_if = Some(fn_refund);
+        Self { ..self }
+    }
+
+    /// Delays limited requests until they fit within the limit instead of rejecting them.
+    ///
+    /// A limited request waits for the retry-after time computed by the store and is then
+    /// checked again. It is rejected only if the wait would exceed `max_delay` or if the
+    /// client already has `max_queue` requests waiting. Queues are shared by the middleware
+    /// of every worker built from the same [`crate::ConfigHandle`], see
+    /// [`crate::RateLimit::with_handle`], so the limit holds across workers; they are
+    /// never shared between processes.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_delay` - Longest time a request may wait for the quota
+    /// * `max_queue` - Maximum number of waiting requests per client
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    ///
+    /// // Let batch clients wait up to 5 seconds, with at most 20 queued requests each
+    /// let config = RateLimitConfig::default()
+    ///     .max_requests(10)
+    ///     .window_secs(1)
+    ///     .delay(Duration::from_secs(5), 20);
+    /// ```
+    pub fn delay(mut self, max_delay: Duration, max_queue: usize) -> Self {
+        self.max_delay = Some(max_delay);
+        self.max_queue = max_queue;
+        Self { ..self }
+    }
+
+    /// Adds a limit shared by all clients on top of the per-client limit.
+    ///
+    /// Both limits are evaluated in the same store check, atomically where the backend
+    /// allows it, and the request is only recorded if it fits within both. Use a distinct
+    /// key for every middleware instance that should have its own global budget, and make
+    /// sure it cannot collide with a client identifier.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - Store key shared by all clients
+    /// * `max_requests` - Maximum number of requests across all clients
+    /// * `window` - Time window duration
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    /// use std::time::Duration;
+    ///
+    /// // 10 requests per second per IP, 50 per 100 milliseconds in total
+    /// let config = RateLimitConfig::default()
+    ///     .max_requests(10)
+    ///     .window_secs(1)
+    ///     .global_limit("global:search", 50, Duration::from_millis(100));
+    /// ```
+    pub fn global_limit(mut self, key: &str, max_requests: usize, window: Duration) -> Self {
+        self.global = Some(Arc::new(GlobalLimit {
+            key: key.to_string(),
+            config: RateLimitConfig::default()
+                .max_requests(max_requests)
+                .window(window),
+        }));
+        Self { ..self }
+    }
+
+    /// Exempts clients from rate limiting.
+    ///
+    /// Requests whose identifier, as returned by `get_id`, is in the list skip every
+    /// check, including bans. Replaces any previously set allowlist.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    ///
+    /// let config = RateLimitConfig::default().allowlist(["127.0.0.1", "10.0.0.5"]);
+    /// assert!(config.allowlist.contains("127.0.0.1"));
+    /// ```
+    pub fn allowlist<I>(mut self, ids: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.allowlist = ids.into_iter().map(Into::into).collect();
+        Self { ..self }
+    }
+
+    /// Adds a per-client limit for requests whose path is `path` or lies under it.
+    ///
+    /// The limit replaces the default one on matching requests, and is counted separately
+    /// from it. Routes are matched by whole path segments, in the order they were added.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path prefix, e.g. `/api/search`
+    /// * `max_requests` - Maximum number of requests per client on the route
+    /// * `window` - Time window duration
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    /// use std::time::Duration;
+    ///
+    /// // 100 requests per minute, but only 5 logins
+    /// let config = RateLimitConfig::default()
+    ///     .max_requests(100)
+    ///     .window_secs(60)
+    ///     .route_limit("/login", 5, Duration::from_secs(60));
+    /// ```
+    pub fn route_limit(mut self, path: &str, max_requests: usize, window: Duration) -> Self {
+        self.routes.push(RouteLimit {
+            path: path.to_string(),
+            config: RateLimitConfig::default()
+                .max_requests(max_requests)
+                .window(window),
+        });
+        Self { ..self }
+    }
+
+    /// Registers an observer notified when requests are allowed or limited and when the
+    /// store fails.
+    ///
+    /// Observers are called on a background thread, never on the request path.
+    /// See [`crate::observer::RateLimitObserver`] for the available callbacks.
+    ///
+    /// # Arguments
+    ///
+    /// * `observer` - Implementation of [`crate::observer::RateLimitObserver`]
+    pub fn observer(mut self, observer: impl RateLimitObserver + 'static) -> Self {
+        self.observers.push(Arc::new(observer));
+        Self { ..self }
+    }
+
+    /// Enables shadow mode, recording limits without ever rejecting requests.
+    ///
+    /// The store is consulted and counts are recorded as usual, but requests over the
+    /// limit are forwarded to the inner service. They are reported with a warning log,
+    /// a `would_limit` metric decision and [`crate::observer::RateLimitObserver::on_would_limit`].
+    /// Useful to see who would be limited before rolling out new limits.
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - Optional response header set to `would-limit` on such requests
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web::http::header::HeaderName;
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    ///
+    /// let config = RateLimitConfig::default()
+    ///     .max_requests(100)
+    ///     .shadow(Some(HeaderName::from_static("x-ratelimit-shadow")));
+    /// ```
+    pub fn shadow(mut self, header: Option<HeaderName>) -> Self {
+        self.shadow = true;
+        self.shadow_header = header;
+        Self { ..self }
+    }
+
+    /// Sets whether requests are let through when the store cannot be consulted.
+    ///
+    /// By default the middleware fails open: a store error or timeout is logged and the
+    /// request is allowed, so a broken backend never takes the service down. Failing
+    /// closed rejects such requests with HTTP 503 instead, for endpoints where letting
+    /// abuse through is worse than an outage. Errors recording a request after the
+    /// response, see [`RateLimitConfig::count_if`] and [`RateLimitConfig::refund_if`],
+    /// are only logged either way.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    ///
+    /// let config = RateLimitConfig::default().fail_open(false);
+    /// assert!(!config.fail_open);
+    /// ```
+    pub fn fail_open(mut self, fail_open: bool) -> Self {
+        self.fail_open = fail_open;
+        Self { ..self }
+    }
+}
+
+/// Configuration for the concurrency limiting middleware.
+///
+/// Caps the number of requests a single client may have in flight at the same time,
+/// independently of how many requests it sends per time window.
+///
+/// # Example
+///
+/// ```rust
+/// use actix_web_ratelimit::config::ConcurrencyConfig;
+///
+/// // At most 3 concurrent report generations per user
+/// let config = ConcurrencyConfig::default()
+///     .max_concurrent(3)
+///     .id(|req| {
+///         req.headers()
+///             .get("X-User-ID")
+///             .and_then(|h| h.to_str().ok())
+///             .unwrap_or("guest")
+///             .to_string()
+///     });
+/// ```
+#[derive(Clone)]
+pub struct ConcurrencyConfig {
+    /// Maximum number of requests a client may have in flight
+    pub max_concurrent: usize,
+    /// How long a permit stays valid if it is never released.
+    /// Only used by backends shared between processes, which may crash while holding it.
+    pub lease_secs: Duration,
+    /// Function to extract client identifier from the request.
+    pub get_id: fn(req: &ServiceRequest) -> String,
+    /// Function called when the client has too many requests in flight.
+    /// Receives the client ID, configuration, and request, returns the HTTP response.
+    pub on_exceed:
+        fn(id: &String, config: &ConcurrencyConfig, req: &ServiceRequest) -> HttpResponse,
+}
+
+impl Default for ConcurrencyConfig {
+    /// Creates a default concurrency limiting configuration.
+    ///
+    /// # Default Values
+    ///
+    /// - `max_concurrent`: 5 requests
+    /// - `lease_secs`: 60 seconds
+    /// - `get_id`: Extracts real IP address from connection info
+    /// - `on_exceed`: Returns HTTP 429 "Too Many Requests" with plain text body
+    fn default() -> Self {
+        Self {
+            max_concurrent: 5,
+            lease_secs: Duration::from_secs(60),
+            get_id: |req| {
+                req.connection_info()
+                    .realip_remote_addr()
+                    .unwrap_or("-")
+                    .to_string()
+            },
+            on_exceed: |_id, _config, _req| {
+                HttpResponse::TooManyRequests().body("Too many concurrent requests")
+            },
+        }
+    }
+}
+
+impl ConcurrencyConfig {
+    /// Sets the maximum number of requests a client may have in flight.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - Maximum number of concurrent requests (must be > 0)
+    pub fn max_concurrent(mut self, value: usize) -> Self {
+        self.max_concurrent = value;
+        Self { ..self }
+    }
+
+    /// Sets how long, in seconds, a permit stays valid if it is never released.
+    ///
+    /// Should be longer than the slowest request on the protected routes.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - Lease duration in seconds
+    pub fn lease_secs(mut self, value: u64) -> Self {
+        self.lease_secs = Duration::from_secs(value);
+        Self { ..self }
+    }
+
+    /// Sets how long a permit stays valid if it is never released, with sub-second
+    /// precision.
+    ///
+    /// Backends keep leases in milliseconds; shorter durations are rounded down.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - Lease duration
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::ConcurrencyConfig;
+    /// use std::time::Duration;
+    ///
+    /// let config = ConcurrencyConfig::default().lease(Duration::from_millis(1500));
+    /// assert_eq!(config.lease_secs, Duration::from_millis(1500));
+    /// ```
+    pub fn lease(mut self, value: Duration) -> Self {
+        self.lease_secs = value;
+        Self { ..self }
+    }
+
+    /// Sets a custom function to extract client identifier from requests.
+    ///
+    /// # Arguments
+    ///
+    /// * `fn_id` - Function that takes a `ServiceRequest` and returns a client identifier string
+    pub fn id(mut self, fn_id: fn(req: &ServiceRequest) -> String) -> Self {
+        self.get_id = fn_id;
+        Self { ..self }
+    }
+
+    /// Sets a custom function to handle requests over the concurrency limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `fn_exceed` - Function that takes client ID, config, and request, returns HTTP response
+    pub fn exceeded(
+        mut self,
+        fn_exceed: fn(
+            id: &String,
+            config: &ConcurrencyConfig,
+            req: &ServiceRequest,
+        ) -> HttpResponse,
+    ) -> Self {
+        self.on_exceed = fn_exceed;
+        Self { ..self }
+    }
 }
@@ -0,0 +1,162 @@
+use std::sync::{
+    Arc, PoisonError, RwLock,
+    atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    config::RateLimitConfig,
+    observer::{Dispatcher, same_observers},
+};
+
+/// Identifier of the next handle created
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Configuration in effect, along with the notifier of its observers.
+pub(crate) struct Active {
+    pub(crate) config: Arc<RateLimitConfig>,
+    pub(crate) dispatcher: Option<Arc<Dispatcher>>,
+}
+
+/// Shared handle to the configuration of [`crate::RateLimit`] middleware, allowing it to
+/// be replaced at runtime.
+///
+/// Every request reads the configuration once when it starts, so it is handled entirely
+/// under either the old or the new version. Counters live in the store and are kept
+/// across updates. Observers are restarted only if the new configuration registers
+/// different ones.
+///
+/// Create the handle outside the `HttpServer` factory and pass clones of it to
+/// [`crate::RateLimit::with_handle`], so that all workers share it.
+///
+/// # Example
+///
+/// ```rust, no_run
+/// # use actix_web::{App, HttpServer, Responder, web};
+/// # use actix_web_ratelimit::{ConfigHandle, RateLimit, config::RateLimitConfig, store::MemoryStore};
+/// # use std::sync::Arc;
+/// #
+/// # async fn index() -> impl Responder {
+/// #     "Hello world!"
+/// # }
+/// #
+/// # #[actix_web::main]
+/// # async fn main() -> std::io::Result<()> {
+/// let handle = ConfigHandle::new(RateLimitConfig::default().max_requests(3).window_secs(10));
+/// let store = Arc::new(MemoryStore::new());
+///
+/// // Later, e.g. from an admin endpoint or a signal handler
+/// let updater = handle.clone();
+/// std::thread::spawn(move || {
+///     updater.modify(|config| config.max_requests(100));
+/// });
+///
+/// HttpServer::new(move || {
+///     App::new()
+///         .wrap(RateLimit::with_handle(handle.clone(), store.clone()))
+///         .route("/", web::get().to(index))
+/// })
+/// .bind(("127.0.0.1", 8080))?
+/// .run()
+/// .await
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ConfigHandle {
+    active: Arc<RwLock<Arc<Active>>>,
+    /// Identifier shared by the clones of the handle
+    id: u64,
+}
+
+impl ConfigHandle {
+    /// Creates a handle holding `config`, starting its observers.
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            active: Arc::new(RwLock::new(Arc::new(Active {
+                dispatcher: Dispatcher::start(&config.observers),
+                config: Arc::new(config),
+            }))),
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the configuration currently in effect.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::{ConfigHandle, config::RateLimitConfig};
+    ///
+    /// let handle = ConfigHandle::new(RateLimitConfig::default().max_requests(5));
+    /// assert_eq!(handle.config().max_requests, 5);
+    /// ```
+    pub fn config(&self) -> Arc<RateLimitConfig> {
+        self.load().config.clone()
+    }
+
+    /// Replaces the configuration, taking effect for requests starting afterwards.
+    pub fn update(&self, config: RateLimitConfig) {
+        self.modify(|_| config);
+    }
+
+    /// Replaces the configuration by applying `f` to a copy of the current one.
+    ///
+    /// Concurrent modifications are applied one after the other, so none is lost.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::{ConfigHandle, config::RateLimitConfig};
+    ///
+    /// let handle = ConfigHandle::new(RateLimitConfig::default());
+    /// handle.modify(|config| config.max_requests(20).allowlist(["127.0.0.1"]));
+    /// assert_eq!(handle.config().max_requests, 20);
+    /// ```
+    pub fn modify(&self, f: impl FnOnce(RateLimitConfig) -> RateLimitConfig) {
+        let mut active = self.active.write().unwrap_or_else(PoisonError::into_inner);
+        let config = f(RateLimitConfig::clone(&active.config));
+
+        let dispatcher = if same_observers(&config.observers, &active.config.observers) {
+            active.dispatcher.clone()
+        } else {
+            Dispatcher::start(&config.observers)
+        };
+
+        *active = Arc::new(Active {
+            config: Arc::new(config),
+            dispatcher,
+        });
+    }
+
+    /// Applies a policy on top of the current configuration, see
+    /// [`crate::policy::Policy::apply`] (requires `serde` feature).
+    ///
+    /// The configuration is left unchanged if the policy cannot be applied.
+    #[cfg(feature = "serde")]
+    pub fn apply_policy(
+        &self,
+        policy: &crate::policy::Policy,
+    ) -> Result<(), crate::policy::PolicyError> {
+        let mut result = Ok(());
+        self.modify(|config| match policy.apply(config.clone()) {
+            Ok(config) => config,
+            Err(err) => {
+                result = Err(err);
+                config
+            }
+        });
+        result
+    }
+
+    /// Returns the identifier shared by the clones of this handle.
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the configuration in effect along with its observers.
+    pub(crate) fn load(&self) -> Arc<Active> {
+        self.active
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
@@ -157,21 +157,103 @@ Then you can use Redis as the storage backend:
 
 ## Storage Backends
 
-This crate provides two built-in storage implementations:
-
-- [`store::MemoryStore`] - In-memory storage using [`dashmap::DashMap`]
-- [`store::RedisStore`] - Distributed storage using Redis (requires `redis` feature)
+This crate provides the following built-in storage implementations:
+
+- [`store::MemoryStore`] - In-memory storage using [`dashmap::DashMap`], which can be
+  saved to a file on shutdown and restored on startup
+- [`store::ShardedMemoryStore`] - In-memory storage counting fixed windows with atomic
+  counters, for hot clients and large limits
+- [`store::RedisStore`] - Distributed storage using Redis (requires `redis` feature), or
+  a Redis Cluster through [`store::RedisStore::new_cluster`] (requires `redis-cluster` feature),
+  or the primary behind Sentinel through [`store::RedisStore::new_sentinel`] (requires
+  `redis-sentinel` feature)
+- [`store::MemcachedStore`] - Distributed storage using memcached, with a fixed window or
+  token bucket algorithm (requires `memcached` feature)
+- [`store::SqlStore`] - Distributed storage in a SQLite or PostgreSQL database (requires
+  `sqlite` or `postgres` feature)
 
 For custom storage backends, implement the [`store::RateLimitStore`] trait.
 
+[`store::CircuitBreaker`] wraps any store and skips it for a cool-down after repeated
+errors, serving requests from a fallback store such as a [`store::MemoryStore`] in the
+meantime, so a degraded Redis does not cost every request a timeout.
+
+[`store::HybridStore`] decides locally and syncs usage to a remote store such as Redis
+in the background, trading a bounded amount of accuracy for far fewer round trips.
+[`store::BatchingStore`] keeps decisions exact and instead sends the checks of
+concurrent requests to the store together, as one pipeline with Redis.
+
+Besides the checks made by the middleware, stores can be driven programmatically:
+[`store::RateLimitStore::peek`] reads a client's state without recording a request,
+`reset`, `reset_prefix` and `reset_all` clear recorded requests, and `snapshot` lists
+the current usage of every client.
+
+## Concurrency Limiting
+
+[`ConcurrencyLimit`] caps the number of requests each client may have in flight,
+configured by [`config::ConcurrencyConfig`]. `MemoryStore` and `RedisStore` implement
+[`store::ConcurrencyStore`]; `RedisStore` holds permits as leases that expire after
+`lease_secs`, so a crashed instance cannot block a client forever. Permits are held
+until the response body has been sent.
+
 ## Configuration
 
 Rate limiting behavior is controlled by [`config::RateLimitConfig`]:
 
 - `max_requests` - Maximum requests allowed within the time window
-- `window_secs` - Duration of the sliding time window in seconds
+- `window_secs` - Duration of the sliding time window, in whole seconds or any [`std::time::Duration`]
+- [`config::RateLimitConfig::from_rate`] - Limit and window from a string such as `"100/min"` or `"5r/s burst=10"`, see [`rate`]
 - `get_id` - Function to extract client identifier from requests
+- `get_cost` - Function to compute how many units of the quota a request consumes
 - `on_exceed` - Function called when rate limit is exceeded
+- `on_banned` - Function called when a banned client sends a request
+- `enforce_bans` - Whether bans are looked up before every check
+- `count_if` / `refund_if` - Optional response status filters for post-response accounting
+- `max_delay` / `max_queue` - Optional delay mode, waiting for the quota instead of rejecting
+- `global` - Optional limit shared by all clients, checked together with the per-client limit
+- `allowlist` - Client identifiers exempt from rate limiting
+- `routes` - Per-client limits replacing the default one under given path prefixes
+- `shadow` - Dry-run mode recording limits without ever rejecting requests
+
+## Policies from Files
+
+With the `serde` feature, [`policy::Policy`] describes limits, windows, key extractor,
+allowlist and route rules in a TOML, YAML or JSON file, optionally overridden by
+`RATELIMIT_*` environment variables, and builds the runtime configuration from it.
+
+## Runtime Updates
+
+[`RateLimit::with_handle`] takes a [`ConfigHandle`] that can replace the configuration
+while the server runs, e.g. to tune limits, allowlists and route rules. Requests pick
+up the new version atomically and counters are kept.
+
+## Observers
+
+Implement [`observer::RateLimitObserver`] and register it with
+[`config::RateLimitConfig::observer`] to be notified when requests are allowed or
+limited, when banned clients are rejected and when the store fails. Observers run on
+a background thread.
+
+## Admin Endpoints
+
+With the `admin` feature, [`admin::scope`] builds an actix `Scope` letting support staff
+look up a client's usage, reset it, ban or unban it and list the top consumers. Bans
+are enforced by the middleware if [`config::RateLimitConfig::enforce_bans`] is set,
+which answers banned clients with `on_banned`.
+
+## Metrics
+
+With the `metrics` feature, the middleware counts allowed, limited and errored decisions
+and measures store latency through the [`metrics`](https://docs.rs/metrics) facade,
+labelled by `policy` and the route pattern the request matched. Install any recorder, such as
+`metrics-exporter-prometheus`, to expose them.
+
+## Tracing
+
+With the `tracing` feature, every request runs in a `ratelimit` span carrying the
+policy, client key (hashed if [`config::RateLimitConfig::hash_keys`] is set), store
+backend, decision and remaining quota, and every store call runs in a
+`ratelimit.store` span.
 
 ## Related Resources
 
@@ -181,29 +263,51 @@ Rate limiting behavior is controlled by [`config::RateLimitConfig`]:
 - [Examples](https://github.com/bigyao25/actix-web-ratelimit/tree/main/examples) - Usage examples
 
  */
+#[cfg(feature = "admin")]
+pub mod admin;
+mod concurrency;
 pub mod config;
+mod handle;
+pub mod observer;
+#[cfg(feature = "serde")]
+pub mod policy;
+pub mod rate;
 pub mod store;
+mod telemetry;
+
+pub use concurrency::{ConcurrencyLimit, ConcurrencyLimitMiddleware, PermitBody};
+pub use handle::ConfigHandle;
 
 use actix_service::{Service, Transform};
 use actix_web::{
-    Error,
+    Error, HttpMessage,
     body::EitherBody,
     dev::{ServiceRequest, ServiceResponse},
+    http::header::HeaderValue,
+    rt::time::sleep,
 };
+use dashmap::DashMap;
 use futures_util::future::{LocalBoxFuture, Ready, ok};
+use log::{debug, error, warn};
 use std::{
-    sync::Arc,
+    rc::Rc,
+    sync::{Arc, LazyLock},
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
-use crate::{config::RateLimitConfig, store::RateLimitStore};
+use crate::{
+    config::RateLimitConfig,
+    store::{RateLimitState, RateLimitStore, StoreError, StoreResult},
+    telemetry::{CheckSpan, Decision, record_decision, route_label, timed, timed_async},
+};
 
 pub struct RateLimit<S>
 where
     S: RateLimitStore,
 {
     store: Arc<S>,
-    config: Arc<RateLimitConfig>,
+    handle: ConfigHandle,
 }
 
 impl<S> RateLimit<S>
@@ -211,11 +315,22 @@ where
     S: RateLimitStore,
 {
     pub fn new(config: RateLimitConfig, store: S) -> Self {
+        Self::with_handle(ConfigHandle::new(config), store)
+    }
+
+    /// Creates the middleware reading its configuration from `handle`, so that it can be
+    /// updated at runtime. See [`ConfigHandle`].
+    pub fn with_handle(handle: ConfigHandle, store: S) -> Self {
         Self {
             store: Arc::new(store),
-            config: Arc::new(config),
+            handle,
         }
     }
+
+    /// Returns the handle to the configuration of this middleware.
+    pub fn handle(&self) -> ConfigHandle {
+        self.handle.clone()
+    }
 }
 
 impl<S, B, ST> Transform<S, ServiceRequest> for RateLimit<ST>
@@ -233,17 +348,118 @@ where
 
     fn new_transform(&self, service: S) -> Self::Future {
         ok(RateLimitMiddleware {
-            service,
+            service: Rc::new(service),
             store: self.store.clone(),
-            config: self.config.clone(),
+            handle: self.handle.clone(),
         })
     }
 }
 
 pub struct RateLimitMiddleware<S> {
-    service: S,
+    service: Rc<S>,
     store: Arc<dyn RateLimitStore>,
-    config: Arc<RateLimitConfig>,
+    handle: ConfigHandle,
+}
+
+/// Logs a store error, quietly if the store is skipped by an open circuit breaker, whose
+/// opening has already been logged.
+fn log_store_error(key: &str, err: &StoreError) {
+    match err {
+        StoreError::CircuitOpen => debug!("Rate limit store skipped for key({}): {}", key, err),
+        _ => error!("Rate limit store error for key({}): {}", key, err),
+    }
+}
+
+/// Response to requests rejected because the store failed and the middleware fails
+/// closed, see [`RateLimitConfig::fail_open`].
+fn unavailable() -> actix_web::HttpResponse {
+    actix_web::HttpResponse::ServiceUnavailable().body("Rate limit unavailable")
+}
+
+/// Checks the quota before the inner service runs.
+///
+/// Only looks at the quota without recording if the request is counted after the response.
+async fn pre_check(
+    store: &dyn RateLimitStore,
+    rules: &[(&str, &RateLimitConfig)],
+    cost: usize,
+    config: &RateLimitConfig,
+    route: &str,
+) -> StoreResult<RateLimitState> {
+    match config.count_if {
+        Some(_) => timed(config, route, store.backend(), "peek", || {
+            rules
+                .iter()
+                .try_fold(RateLimitState::UNLIMITED, |state, &(key, rule)| {
+                    Ok(state.most_restrictive(store.peek(key, cost, rule)?))
+                })
+        }),
+        None => {
+            let check = store.check_all_async(rules, cost);
+            timed_async(config, route, store.backend(), "check_all", check).await
+        }
+    }
+}
+
+/// Number of delayed requests per [`ConfigHandle`] and client, shared by the middleware
+/// of every worker holding the same handle, so that `max_queue` holds for the whole server
+static QUEUES: LazyLock<DashMap<(u64, String), usize>> = LazyLock::new(DashMap::new);
+
+/// Place of a delayed request in its client's queue, freed when dropped.
+struct QueueSlot {
+    key: (u64, String),
+}
+
+impl QueueSlot {
+    /// Takes a place in the client's queue of the middleware with handle `handle` if it
+    /// holds fewer than `max_queue` requests.
+    fn acquire(handle: u64, key: &str, max_queue: usize) -> Option<Self> {
+        let key = (handle, key.to_string());
+        let mut len = QUEUES.entry(key.clone()).or_default();
+        if *len >= max_queue {
+            return None;
+        }
+        *len += 1;
+        Some(Self { key })
+    }
+}
+
+impl Drop for QueueSlot {
+    fn drop(&mut self) {
+        QUEUES.remove_if_mut(&self.key, |_, len| {
+            *len = len.saturating_sub(1);
+            *len == 0
+        });
+    }
+}
+
+/// Waits until a limited request fits within the limit.
+///
+/// Gives up and returns the last limited state if delay mode is disabled, the client's
+/// queue is full or the next retry would end after `max_delay`. The first rule is the
+/// per-client one, whose key identifies the queue.
+async fn wait_for_quota(
+    handle: u64,
+    store: &dyn RateLimitStore,
+    rules: &[(&str, &RateLimitConfig)],
+    cost: usize,
+    config: &RateLimitConfig,
+    route: &str,
+    mut state: RateLimitState,
+) -> StoreResult<RateLimitState> {
+    let Some(max_delay) = config.max_delay else {
+        return Ok(state);
+    };
+    let Some(_slot) = QueueSlot::acquire(handle, rules[0].0, config.max_queue) else {
+        return Ok(state);
+    };
+
+    let started = Instant::now();
+    while state.limited && started.elapsed().saturating_add(state.retry_after) <= max_delay {
+        sleep(state.retry_after.max(Duration::from_millis(1))).await;
+        state = pre_check(store, rules, cost, config, route).await?;
+    }
+    Ok(state)
 }
 
 impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
@@ -261,18 +477,154 @@ where
     }
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        let ip = (self.config.get_id)(&req);
-
-        if self.store.is_limited(&ip, &self.config) {
-            let res = (self.config.on_exceed)(&ip, &self.config, &req);
-            let res = req.into_response(res).map_into_right_body();
-            return Box::pin(async { Ok(res) });
+        let service = self.service.clone();
+        let store = self.store.clone();
+        let active = self.handle.load();
+        let config = active.config.clone();
+        let dispatcher = active.dispatcher.clone();
+        let handle = self.handle.id();
+
+        let ip = (config.get_id)(&req);
+        if config.allowlist.contains(&ip) {
+            let fut = service.call(req);
+            return Box::pin(async move { Ok(fut.await?.map_into_left_body()) });
         }
 
-        let fut = self.service.call(req);
-        Box::pin(async move {
-            let res = fut.await?;
+        let cost = (config.get_cost)(&req);
+        let span = CheckSpan::new(&config, &ip, store.backend());
+        let pattern = route_label(&req);
+
+        let fut = span.clone().instrument(async move {
+            // The per-client limit, or the one of the first matching route
+            let route = config
+                .routes
+                .iter()
+                .find(|route| route.matches(req.path()))
+                .map(|route| (route.key(&ip), &route.config));
+            let mut rules: Vec<(&str, &RateLimitConfig)> = match &route {
+                Some((key, rule)) => vec![(key, rule)],
+                None => vec![(&ip, &config)],
+            };
+            // Plus the limit shared by all clients if configured
+            if let Some(global) = &config.global {
+                rules.push((&global.key, &global.config));
+            }
+
+            let banned = if config.enforce_bans {
+                timed(&config, &pattern, store.backend(), "is_banned", || {
+                    store.is_banned(&ip)
+                })
+            } else {
+                Ok(false)
+            };
+            let mut result = match banned {
+                Ok(true) if !config.shadow => {
+                    span.record_banned();
+                    record_decision(&config, &pattern, Decision::Banned);
+                    if let Some(dispatcher) = &dispatcher {
+                        dispatcher.banned(&config.policy, &ip);
+                    }
+                    let res = (config.on_banned)(&ip, &config, &req);
+                    return Ok(req.into_response(res).map_into_right_body());
+                }
+                Ok(banned) => {
+                    if banned {
+                        warn!(
+                            "Banned key({}) let through under policy({}) in shadow mode",
+                            ip, config.policy
+                        );
+                    }
+                    pre_check(&*store, &rules, cost, &config, &pattern).await
+                }
+                // A failed ban lookup is handled like a failed check
+                Err(err) => Err(err),
+            };
+            // Slow the client down instead of rejecting it if delay mode is enabled
+            if let Ok(state) = result
+                && state.limited
+                && !config.shadow
+            {
+                result =
+                    wait_for_quota(handle, &*store, &rules, cost, &config, &pattern, state).await;
+            }
+            let checked_at = Instant::now();
+
+            let decision = Decision::of(&result, config.shadow);
+            span.record(decision, &result);
+            record_decision(&config, &pattern, decision);
+            if let Some(dispatcher) = &dispatcher {
+                dispatcher.decision(&config.policy, &ip, &result, config.shadow);
+            }
+            let counted = match result {
+                Ok(state) => {
+                    req.extensions_mut().insert(state);
+                    if state.limited {
+                        if !config.shadow {
+                            let res = (config.on_exceed)(&ip, &config, &req);
+                            return Ok(req.into_response(res).map_into_right_body());
+                        }
+                        warn!(
+                            "Rate limit would have been exceeded for key({}) under policy({})",
+                            ip, config.policy
+                        );
+                    }
+                    // How long the units recorded stay in the window they were counted in
+                    (config.count_if.is_none() && !state.limited).then_some(state.reset_after)
+                }
+                // Fallback: allow request when the store fails (graceful degradation),
+                // unless configured to fail closed
+                Err(err) => {
+                    log_store_error(&ip, &err);
+                    if !config.fail_open && !config.shadow {
+                        return Ok(req.into_response(unavailable()).map_into_right_body());
+                    }
+                    None
+                }
+            };
+
+            // Errors of the inner service are counted and refunded by the status of the
+            // response they render to, before being passed on
+            let result = service.call(req).await;
+            let status = match &result {
+                Ok(res) => res.status(),
+                Err(err) => err.error_response().status(),
+            };
+
+            if let Some(count_if) = config.count_if
+                && count_if(status)
+            {
+                let check = store.check_all_async(&rules, cost);
+                if let Err(err) =
+                    timed_async(&config, &pattern, store.backend(), "check_all", check).await
+                {
+                    log_store_error(&ip, &err);
+                }
+            }
+            // Once the window the request was counted in has ended, a refund would take
+            // units from the next one instead
+            if let Some(reset_after) = counted
+                && checked_at.elapsed() < reset_after
+                && let Some(refund_if) = config.refund_if
+                && refund_if(status)
+            {
+                for &(key, rule) in &rules {
+                    if let Err(err) = timed(&config, &pattern, store.backend(), "refund", || {
+                        store.refund(key, cost, rule)
+                    }) {
+                        log_store_error(key, &err);
+                    }
+                }
+            }
+
+            let mut res = result?;
+            if decision == Decision::WouldLimit
+                && let Some(header) = &config.shadow_header
+            {
+                res.headers_mut()
+                    .insert(header.clone(), HeaderValue::from_static("would-limit"));
+            }
             Ok(res.map_into_left_body())
-        })
+        });
+        Box::pin(fut)
     }
 }
@@ -0,0 +1,296 @@
+//! Observers notified of rate limiter events.
+//!
+//! Register a [`RateLimitObserver`] with [`crate::config::RateLimitConfig::observer`] to
+//! publish limiter decisions to an external system, e.g. a security pipeline.
+//! Events are handed to a background thread, so observers never block the request path;
+//! if observers fall too far behind, new events are dropped and the number of dropped
+//! events is logged at most once per second.
+use dashmap::DashMap;
+use log::{error, warn};
+use std::{
+    panic::{AssertUnwindSafe, catch_unwind},
+    sync::{
+        Arc, Mutex, PoisonError, Weak,
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{SyncSender, TrySendError, sync_channel},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::store::{RateLimitState, StoreResult};
+
+/// Maximum number of events waiting for the observers before new ones are dropped
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Number of limited clients tracked before expired ones are first pruned
+const MIN_PRUNE_AT: usize = 1024;
+
+/// Minimum time between two warnings about dropped events
+const DROP_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Running dispatchers, reused by middleware registering the same observers
+static DISPATCHERS: Mutex<Vec<Weak<Dispatcher>>> = Mutex::new(Vec::new());
+
+/// Client and policy an event refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventInfo {
+    /// Name of the policy, see [`crate::config::RateLimitConfig::policy`]
+    pub policy: String,
+    /// Client identifier returned by `get_id`
+    pub key: String,
+}
+
+/// Callbacks for the lifecycle of rate limited clients.
+///
+/// All methods have empty default implementations, so observers only implement the
+/// events they care about. Callbacks run on a background thread shared by every
+/// [`crate::RateLimit`] instance registering the same observers, e.g. one per worker.
+/// A panicking callback is logged and the thread keeps notifying the observers.
+///
+/// # Example
+///
+/// ```rust
+/// use actix_web_ratelimit::config::RateLimitConfig;
+/// use actix_web_ratelimit::observer::{EventInfo, RateLimitObserver};
+/// use actix_web_ratelimit::store::RateLimitState;
+///
+/// struct SecurityPipeline;
+///
+/// impl RateLimitObserver for SecurityPipeline {
+///     fn on_limited(&self, info: &EventInfo, _state: &RateLimitState, first: bool) {
+///         if first {
+///             println!("{} hit the {} limit", info.key, info.policy);
+///         }
+///     }
+/// }
+///
+/// let config = RateLimitConfig::default().observer(SecurityPipeline);
+/// ```
+pub trait RateLimitObserver: Send + Sync {
+    /// Whether [`RateLimitObserver::on_allowed`] should be called.
+    ///
+    /// Allowed requests are by far the most frequent event, so they are only queued if
+    /// an observer returns `true` here. Defaults to `false`.
+    fn observes_allowed(&self) -> bool {
+        false
+    }
+
+    /// Called when a request fits within the limit, if
+    /// [`RateLimitObserver::observes_allowed`] returns `true`.
+    fn on_allowed(&self, _info: &EventInfo, _state: &RateLimitState) {}
+
+    /// Called when a request is rejected because the client exceeded the limit.
+    ///
+    /// `first` is `true` for the first rejection since the client was last allowed.
+    fn on_limited(&self, _info: &EventInfo, _state: &RateLimitState, _first: bool) {}
+
+    /// Called in shadow mode when a request exceeded the limit but was let through.
+    fn on_would_limit(&self, _info: &EventInfo, _state: &RateLimitState) {}
+
+    /// Called when a request is rejected because the client is banned.
+    fn on_banned(&self, _info: &EventInfo) {}
+
+    /// Called when a client is banned through the [`crate::admin`] endpoints, for
+    /// `duration` or permanently if `None`.
+    ///
+    /// Bans made by calling [`crate::store::RateLimitStore::ban`] directly bypass the
+    /// observers.
+    fn on_ban_applied(&self, _info: &EventInfo, _duration: Option<Duration>) {}
+
+    /// Called when the store could not be consulted. The request was let through unless
+    /// the middleware fails closed, see [`crate::config::RateLimitConfig::fail_open`].
+    fn on_store_error(&self, _info: &EventInfo, _error: &str) {}
+}
+
+/// Event queued for the observer thread.
+enum Event {
+    Allowed(EventInfo, RateLimitState),
+    Limited(EventInfo, RateLimitState, bool),
+    WouldLimit(EventInfo, RateLimitState),
+    Banned(EventInfo),
+    BanApplied(EventInfo, Option<Duration>),
+    StoreError(EventInfo, String),
+}
+
+/// Forwards events from the middleware to the observers on a background thread.
+///
+/// The thread stops once the last middleware holding the dispatcher is dropped.
+pub(crate) struct Dispatcher {
+    sender: SyncSender<Event>,
+    observers: Vec<Arc<dyn RateLimitObserver>>,
+    /// Whether any observer is notified of allowed requests
+    observes_allowed: bool,
+    /// Events dropped since the observer thread last reported them
+    dropped: Arc<AtomicUsize>,
+    /// Keys rejected since they were last allowed, with when their window ends, to tell
+    /// the first rejection apart
+    limited: DashMap<String, Instant>,
+    /// Number of tracked keys at which expired ones are pruned next
+    prune_at: AtomicUsize,
+}
+
+impl Dispatcher {
+    /// Returns the dispatcher of `observers`, starting the observer thread unless
+    /// another middleware already did, or `None` if there is nothing to notify.
+    pub(crate) fn start(observers: &[Arc<dyn RateLimitObserver>]) -> Option<Arc<Self>> {
+        if observers.is_empty() {
+            return None;
+        }
+
+        let mut running = DISPATCHERS.lock().unwrap_or_else(PoisonError::into_inner);
+        running.retain(|dispatcher| dispatcher.strong_count() > 0);
+        if let Some(dispatcher) = running
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|dispatcher| same_observers(&dispatcher.observers, observers))
+        {
+            return Some(dispatcher);
+        }
+
+        let notified = observers.to_vec();
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let reported = dropped.clone();
+        let (sender, receiver) = sync_channel::<Event>(QUEUE_CAPACITY);
+        let spawned = std::thread::Builder::new()
+            .name("ratelimit-observer".to_string())
+            .spawn(move || {
+                let mut last_report: Option<Instant> = None;
+                for event in receiver {
+                    for observer in &notified {
+                        notify(observer.as_ref(), &event);
+                    }
+                    if last_report.is_none_or(|at| at.elapsed() >= DROP_REPORT_INTERVAL) {
+                        let count = reported.swap(0, Ordering::Relaxed);
+                        if count > 0 {
+                            warn!("Rate limit observers are lagging, {} events dropped", count);
+                            last_report = Some(Instant::now());
+                        }
+                    }
+                }
+            });
+        if let Err(err) = spawned {
+            error!("Failed to start rate limit observer thread: {}", err);
+            return None;
+        }
+
+        let dispatcher = Arc::new(Self {
+            sender,
+            observers: observers.to_vec(),
+            observes_allowed: observers.iter().any(|observer| observer.observes_allowed()),
+            dropped,
+            limited: DashMap::new(),
+            prune_at: AtomicUsize::new(MIN_PRUNE_AT),
+        });
+        running.push(Arc::downgrade(&dispatcher));
+        Some(dispatcher)
+    }
+
+    /// Notifies the observers of the decision taken before the inner service runs.
+    pub(crate) fn decision(
+        &self,
+        policy: &str,
+        key: &str,
+        result: &StoreResult<RateLimitState>,
+        shadow: bool,
+    ) {
+        if let Ok(state) = result
+            && !state.limited
+        {
+            self.limited.remove(key);
+            if !self.observes_allowed {
+                return;
+            }
+        }
+
+        let info = EventInfo {
+            policy: policy.to_string(),
+            key: key.to_string(),
+        };
+        let event = match result {
+            Ok(state) if state.limited && shadow => Event::WouldLimit(info, *state),
+            Ok(state) if state.limited => {
+                let now = Instant::now();
+                let until = now + state.reset_after.max(state.retry_after);
+                let first = self
+                    .limited
+                    .insert(key.to_string(), until)
+                    .is_none_or(|previous| previous <= now);
+                self.prune(now);
+                Event::Limited(info, *state, first)
+            }
+            Ok(state) => Event::Allowed(info, *state),
+            Err(err) => Event::StoreError(info, err.to_string()),
+        };
+        self.send(event);
+    }
+
+    /// Drops the keys whose window has ended once there are too many of them, so that
+    /// clients limited once and never seen again are not tracked forever.
+    fn prune(&self, now: Instant) {
+        if self.limited.len() < self.prune_at.load(Ordering::Relaxed) {
+            return;
+        }
+        self.limited.retain(|_, until| *until > now);
+        self.prune_at.store(
+            (self.limited.len() * 2).max(MIN_PRUNE_AT),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Notifies the observers that a banned client was rejected.
+    pub(crate) fn banned(&self, policy: &str, key: &str) {
+        self.send(Event::Banned(EventInfo {
+            policy: policy.to_string(),
+            key: key.to_string(),
+        }));
+    }
+
+    /// Notifies the observers that a client was banned.
+    #[cfg_attr(not(feature = "admin"), allow(dead_code))]
+    pub(crate) fn ban_applied(&self, policy: &str, key: &str, duration: Option<Duration>) {
+        self.send(Event::BanApplied(
+            EventInfo {
+                policy: policy.to_string(),
+                key: key.to_string(),
+            },
+            duration,
+        ));
+    }
+
+    fn send(&self, event: Event) {
+        match self.sender.try_send(event) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(TrySendError::Disconnected(_)) => error!("Rate limit observer thread has stopped"),
+        }
+    }
+}
+
+/// Calls the callback of `observer` matching `event`, logging it if it panics so that the
+/// observer thread keeps running.
+fn notify(observer: &dyn RateLimitObserver, event: &Event) {
+    let notified = catch_unwind(AssertUnwindSafe(|| match event {
+        Event::Allowed(info, state) => observer.on_allowed(info, state),
+        Event::Limited(info, state, first) => observer.on_limited(info, state, *first),
+        Event::WouldLimit(info, state) => observer.on_would_limit(info, state),
+        Event::Banned(info) => observer.on_banned(info),
+        Event::BanApplied(info, duration) => observer.on_ban_applied(info, *duration),
+        Event::StoreError(info, err) => observer.on_store_error(info, err),
+    }));
+    if notified.is_err() {
+        error!("Rate limit observer panicked, event skipped");
+    }
+}
+
+/// Whether two lists hold the same observers, in the same order.
+pub(crate) fn same_observers(
+    a: &[Arc<dyn RateLimitObserver>],
+    b: &[Arc<dyn RateLimitObserver>],
+) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|(a, b)| std::ptr::addr_eq(Arc::as_ptr(a), Arc::as_ptr(b)))
+}
@@ -0,0 +1,473 @@
+//! Rate limit policies loaded from files and environment variables (requires `serde` feature).
+//!
+//! A [`Policy`] is the serializable part of a [`RateLimitConfig`]: limits, windows,
+//! key extractor, allowlist and route rules. Load it from a TOML, YAML or JSON
+//! file, override it with `RATELIMIT_*` environment variables, then apply it on top of a
+//! configuration holding the parts that only exist in code, such as `on_exceed` or
+//! observers.
+//!
+//! A policy does not choose the limiting algorithm: it follows from the store the
+//! middleware is given, e.g. a sliding window with [`crate::store::MemoryStore`] or a
+//! token bucket with [`crate::store::MemcachedStore`] configured for it. A policy
+//! setting `algorithm` is rejected like any unknown field.
+//!
+//! # Example
+//!
+//! ```rust
+//! use actix_web_ratelimit::config::RateLimitConfig;
+//! use actix_web_ratelimit::policy::Policy;
+//! use std::time::Duration;
+//!
+//! let policy = Policy::from_toml(
+//!     r#"
+//!     name = "api"
+//!     max_requests = 100
+//!     window_secs = 60
+//!     key = "ip"
+//!     allowlist = ["127.0.0.1"]
+//!
+//!     [[routes]]
+//!     path = "/login"
+//!     max_requests = 5
+//!     window_secs = 60
+//!
+//!     [global]
+//!     key = "global:api"
+//!     max_requests = 50
+//!     window_ms = 100
+//!     "#,
+//! )?;
+//!
+//! let config = policy.apply(RateLimitConfig::default())?;
+//! assert_eq!(config.max_requests, 100);
+//! assert_eq!(config.routes[0].config.max_requests, 5);
+//! assert_eq!(config.global.unwrap().config.window_secs, Duration::from_millis(100));
+//! # Ok::<(), actix_web_ratelimit::policy::PolicyError>(())
+//! ```
+use actix_web::dev::ServiceRequest;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    config::{GlobalLimit, RateLimitConfig, RouteLimit},
+    rate::{Rate, RateParseError},
+};
+
+/// Prefix of the environment variables read by [`Policy::with_env`]
+const ENV_PREFIX: &str = "RATELIMIT_";
+
+/// Function extracting the client identifier from a request, see [`RateLimitConfig::id`]
+pub type KeyExtractor = fn(req: &ServiceRequest) -> String;
+
+/// Serializable description of a rate limit policy.
+///
+/// Every field has a default matching [`RateLimitConfig::default`], so files only need
+/// to list what they change. Unknown fields are rejected to catch typos.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// Name of the policy, see [`RateLimitConfig::policy`]
+    pub name: String,
+    /// Maximum number of requests per client within the window
+    pub max_requests: usize,
+    /// Time window duration in seconds
+    pub window_secs: u64,
+    /// Time window duration in milliseconds, overriding `window_secs`
+    pub window_ms: Option<u64>,
+    /// Rate string overriding `max_requests` and the window, e.g. `"100/min"`,
+    /// see [`crate::rate`]
+    pub rate: Option<String>,
+    /// Name of the key extractor identifying clients, `ip` or `peer` unless custom
+    /// extractors are given to [`Policy::apply_with`]
+    pub key: String,
+    /// Client identifiers never rate limited
+    pub allowlist: Vec<String>,
+    /// Per-client limits replacing the default one under path prefixes
+    pub routes: Vec<RoutePolicy>,
+    /// Limit shared by all clients
+    pub global: Option<GlobalPolicy>,
+    /// Whether limits are only recorded and reported, never enforced
+    pub shadow: bool,
+    /// Whether client identifiers are hashed in traces
+    pub hash_keys: bool,
+}
+
+/// Serializable [`RouteLimit`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoutePolicy {
+    /// Path prefix the limit applies to
+    pub path: String,
+    /// Maximum number of requests per client on the route
+    pub max_requests: usize,
+    /// Time window duration in seconds
+    #[serde(default)]
+    pub window_secs: u64,
+    /// Time window duration in milliseconds, overriding `window_secs`
+    #[serde(default)]
+    pub window_ms: Option<u64>,
+}
+
+/// Serializable [`GlobalLimit`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GlobalPolicy {
+    /// Store key shared by all clients
+    pub key: String,
+    /// Maximum number of requests across all clients
+    pub max_requests: usize,
+    /// Time window duration in seconds
+    #[serde(default)]
+    pub window_secs: u64,
+    /// Time window duration in milliseconds, overriding `window_secs`
+    #[serde(default)]
+    pub window_ms: Option<u64>,
+}
+
+/// Error returned when a [`Policy`] cannot be loaded or applied.
+#[derive(Debug)]
+pub enum PolicyError {
+    /// The policy file could not be read
+    Io(std::io::Error),
+    /// The policy file is not valid TOML or does not describe a policy
+    Toml(toml::de::Error),
+    /// The policy file is not valid YAML or does not describe a policy
+    Yaml(serde_yaml::Error),
+    /// The policy file is not valid JSON or does not describe a policy
+    Json(serde_json::Error),
+    /// The file extension is not one of `toml`, `yaml`, `yml` or `json`
+    UnknownFormat(PathBuf),
+    /// An environment variable is unknown or holds an invalid value
+    Env {
+        /// Name of the variable
+        var: String,
+        /// What is wrong with its value
+        message: String,
+    },
+    /// No key extractor is registered under the name
+    UnknownKey(String),
+    /// The rate string cannot be parsed
+    Rate(RateParseError),
+    /// A window is missing or zero; holds what the window belongs to
+    ZeroWindow(String),
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyError::Io(err) => write!(f, "failed to read policy: {}", err),
+            PolicyError::Toml(err) => write!(f, "invalid TOML policy: {}", err),
+            PolicyError::Yaml(err) => write!(f, "invalid YAML policy: {}", err),
+            PolicyError::Json(err) => write!(f, "invalid JSON policy: {}", err),
+            PolicyError::UnknownFormat(path) => {
+                write!(f, "unknown policy format: {}", path.display())
+            }
+            PolicyError::Env { var, message } => write!(f, "invalid {}: {}", var, message),
+            PolicyError::UnknownKey(name) => write!(f, "unknown key extractor: {}", name),
+            PolicyError::Rate(err) => write!(f, "{}", err),
+            PolicyError::ZeroWindow(what) => {
+                write!(f, "window of {} must be greater than zero", what)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PolicyError::Io(err) => Some(err),
+            PolicyError::Toml(err) => Some(err),
+            PolicyError::Yaml(err) => Some(err),
+            PolicyError::Json(err) => Some(err),
+            PolicyError::Rate(err) => Some(err),
+            PolicyError::UnknownFormat(_)
+            | PolicyError::Env { .. }
+            | PolicyError::UnknownKey(_)
+            | PolicyError::ZeroWindow(_) => None,
+        }
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        let config = RateLimitConfig::default();
+        Self {
+            name: config.policy,
+            max_requests: config.max_requests,
+            window_secs: config.window_secs.as_secs(),
+            window_ms: None,
+            rate: None,
+            key: "ip".to_string(),
+            allowlist: Vec::new(),
+            routes: Vec::new(),
+            global: None,
+            shadow: config.shadow,
+            hash_keys: config.hash_keys,
+        }
+    }
+}
+
+impl Policy {
+    /// Parses a policy from TOML.
+    pub fn from_toml(s: &str) -> Result<Self, PolicyError> {
+        toml::from_str(s).map_err(PolicyError::Toml)
+    }
+
+    /// Parses a policy from YAML.
+    pub fn from_yaml(s: &str) -> Result<Self, PolicyError> {
+        serde_yaml::from_str(s).map_err(PolicyError::Yaml)
+    }
+
+    /// Parses a policy from JSON.
+    pub fn from_json(s: &str) -> Result<Self, PolicyError> {
+        serde_json::from_str(s).map_err(PolicyError::Json)
+    }
+
+    /// Reads a policy from a file, choosing the format from its extension:
+    /// `toml`, `yaml`, `yml` or `json`.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, PolicyError> {
+        let path = path.as_ref();
+        let parse = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml,
+            Some("yaml" | "yml") => Self::from_yaml,
+            Some("json") => Self::from_json,
+            _ => return Err(PolicyError::UnknownFormat(path.to_path_buf())),
+        };
+        parse(&std::fs::read_to_string(path).map_err(PolicyError::Io)?)
+    }
+
+    /// Builds a policy from the defaults overridden by `RATELIMIT_*` environment variables,
+    /// see [`Policy::with_env`].
+    pub fn from_env() -> Result<Self, PolicyError> {
+        Self::default().with_env()
+    }
+
+    /// Overrides the policy with the `RATELIMIT_*` environment variables that are set:
+    ///
+    /// - `RATELIMIT_NAME`
+    /// - `RATELIMIT_MAX_REQUESTS`
+    /// - `RATELIMIT_WINDOW_SECS`
+    /// - `RATELIMIT_WINDOW_MS`
+    /// - `RATELIMIT_RATE`, e.g. `100/min`
+    /// - `RATELIMIT_KEY`, the name of the key extractor
+    /// - `RATELIMIT_ALLOWLIST`, comma separated client identifiers
+    /// - `RATELIMIT_SHADOW`, `true` or `false`
+    /// - `RATELIMIT_HASH_KEYS`, `true` or `false`
+    ///
+    /// Route rules and the global limit can only be set in files. Other variables starting
+    /// with `RATELIMIT_` are rejected to catch typos, and variables whose name is not
+    /// valid UTF-8 are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`PolicyError::Env`] naming the first variable that is unknown or holds an
+    /// invalid value, including one that is not valid UTF-8.
+    pub fn with_env(self) -> Result<Self, PolicyError> {
+        let mut vars = Vec::new();
+        for (var, value) in std::env::vars_os() {
+            let Some(var) = var.to_str().filter(|var| var.starts_with(ENV_PREFIX)) else {
+                continue;
+            };
+            let value = value.into_string().map_err(|_| PolicyError::Env {
+                var: var.to_string(),
+                message: "value is not valid UTF-8".to_string(),
+            })?;
+            vars.push((var.to_string(), value));
+        }
+        self.with_vars(vars)
+    }
+
+    fn with_vars(
+        mut self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, PolicyError> {
+        for (var, value) in vars {
+            let Some(name) = var.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let invalid = |message: &dyn fmt::Display| PolicyError::Env {
+                var: var.clone(),
+                message: message.to_string(),
+            };
+            match name {
+                "NAME" => self.name = value,
+                "MAX_REQUESTS" => self.max_requests = value.parse().map_err(|e| invalid(&e))?,
+                "WINDOW_SECS" => self.window_secs = value.parse().map_err(|e| invalid(&e))?,
+                "WINDOW_MS" => self.window_ms = Some(value.parse().map_err(|e| invalid(&e))?),
+                "RATE" => {
+                    value.parse::<Rate>().map_err(|e| invalid(&e))?;
+                    self.rate = Some(value)
+                }
+                "KEY" => self.key = value,
+                "ALLOWLIST" => {
+                    self.allowlist = value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|id| !id.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                }
+                "SHADOW" => self.shadow = value.parse().map_err(|e| invalid(&e))?,
+                "HASH_KEYS" => self.hash_keys = value.parse().map_err(|e| invalid(&e))?,
+                _ => return Err(invalid(&"unknown variable")),
+            }
+        }
+        Ok(self)
+    }
+
+    /// Applies the policy on top of `base` with the built-in key extractors, see
+    /// [`Policy::apply_with`].
+    pub fn apply(&self, base: RateLimitConfig) -> Result<RateLimitConfig, PolicyError> {
+        self.apply_with(base, &[])
+    }
+
+    /// Applies the policy on top of `base`, keeping the parts of `base` a policy cannot
+    /// describe, such as `on_exceed`, `get_cost` or observers.
+    ///
+    /// Route rules and the allowlist replace those of `base`. The key extractor is looked
+    /// up by name among `extractors`, then among the built-in ones:
+    ///
+    /// - `ip`: the real IP address, honouring `Forwarded` and `X-Forwarded-For` headers
+    /// - `peer`: the IP address of the peer socket
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web::dev::ServiceRequest;
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    /// use actix_web_ratelimit::policy::Policy;
+    ///
+    /// fn api_key(req: &ServiceRequest) -> String {
+    ///     req.headers()
+    ///         .get("X-Api-Key")
+    ///         .and_then(|h| h.to_str().ok())
+    ///         .unwrap_or("anonymous")
+    ///         .to_string()
+    /// }
+    ///
+    /// let policy = Policy::from_json(r#"{"key": "api_key", "max_requests": 1000}"#)?;
+    /// let config = policy.apply_with(RateLimitConfig::default(), &[("api_key", api_key)])?;
+    ///
+    /// // Unknown extractors are reported
+    /// let policy = Policy::from_json(r#"{"key": "user"}"#)?;
+    /// assert!(policy.apply(RateLimitConfig::default()).is_err());
+    /// # Ok::<(), actix_web_ratelimit::policy::PolicyError>(())
+    /// ```
+    pub fn apply_with(
+        &self,
+        base: RateLimitConfig,
+        extractors: &[(&str, KeyExtractor)],
+    ) -> Result<RateLimitConfig, PolicyError> {
+        let get_id = extractors
+            .iter()
+            .find(|&&(name, _)| name == self.key)
+            .map(|&(_, extractor)| extractor)
+            .or_else(|| builtin_extractor(&self.key))
+            .ok_or_else(|| PolicyError::UnknownKey(self.key.clone()))?;
+
+        let mut config = base
+            .policy(&self.name)
+            .hash_keys(self.hash_keys)
+            .max_requests(self.max_requests)
+            .window(window(self.window_secs, self.window_ms, || {
+                format!("policy {}", self.name)
+            })?)
+            .id(get_id)
+            .allowlist(self.allowlist.iter().cloned());
+        if let Some(rate) = &self.rate {
+            config = config.rate(rate.parse::<Rate>().map_err(PolicyError::Rate)?);
+        }
+        config.routes = self
+            .routes
+            .iter()
+            .map(|route| {
+                let window = window(route.window_secs, route.window_ms, || {
+                    format!("route {}", route.path)
+                })?;
+                Ok(RouteLimit {
+                    path: route.path.clone(),
+                    config: RateLimitConfig::default()
+                        .max_requests(route.max_requests)
+                        .window(window),
+                })
+            })
+            .collect::<Result<_, PolicyError>>()?;
+        config.global = match &self.global {
+            Some(global) => {
+                let window = window(global.window_secs, global.window_ms, || {
+                    format!("global limit {}", global.key)
+                })?;
+                Some(Arc::new(GlobalLimit {
+                    key: global.key.clone(),
+                    config: RateLimitConfig::default()
+                        .max_requests(global.max_requests)
+                        .window(window),
+                }))
+            }
+            None => None,
+        };
+        config.shadow = self.shadow;
+        Ok(config)
+    }
+
+    /// Builds a configuration from the policy on top of [`RateLimitConfig::default`].
+    pub fn build(&self) -> Result<RateLimitConfig, PolicyError> {
+        self.apply(RateLimitConfig::default())
+    }
+}
+
+/// Window given in milliseconds, or else in seconds, rejecting an empty one.
+fn window(
+    secs: u64,
+    ms: Option<u64>,
+    what: impl FnOnce() -> String,
+) -> Result<Duration, PolicyError> {
+    match ms.map_or(Duration::from_secs(secs), Duration::from_millis) {
+        Duration::ZERO => Err(PolicyError::ZeroWindow(what())),
+        window => Ok(window),
+    }
+}
+
+/// Looks up a built-in key extractor by name.
+fn builtin_extractor(name: &str) -> Option<KeyExtractor> {
+    match name {
+        "ip" => Some(RateLimitConfig::default().get_id),
+        "peer" => Some(peer_ip),
+        _ => None,
+    }
+}
+
+/// Identifies clients by the IP address of the peer socket.
+fn peer_ip(req: &ServiceRequest) -> String {
+    req.peer_addr()
+        .map_or_else(|| "-".to_string(), |addr| addr.ip().to_string())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    #[test]
+    fn non_utf8_variables() {
+        let invalid = OsStr::from_bytes(b"\xff");
+        // SAFETY: no other test reads or writes the environment
+        unsafe { std::env::set_var("POLICY_TEST_UNRELATED", invalid) };
+        assert!(Policy::from_env().is_ok());
+
+        // SAFETY: as above
+        unsafe { std::env::set_var("RATELIMIT_NAME", invalid) };
+        let result = Policy::from_env();
+        // SAFETY: as above
+        unsafe {
+            std::env::remove_var("RATELIMIT_NAME");
+            std::env::remove_var("POLICY_TEST_UNRELATED");
+        }
+        assert!(matches!(result, Err(PolicyError::Env { var, .. }) if var == "RATELIMIT_NAME"));
+    }
+}
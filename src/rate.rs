@@ -0,0 +1,163 @@
+//! Human-readable rate strings such as `"100/min"` or `"5r/s burst=10"`.
+//!
+//! A rate is a request count followed by a window, separated by `/` or `per`:
+//!
+//! - `100/m`, `100/min`, `100 per minute`
+//! - `10/s`, `10 per second`, `10r/s` (nginx style)
+//! - `5000/hour`, `1000/day`
+//! - `50/500ms`, `30/10s`: the window may be prefixed by a count
+//!
+//! Recognized units are `ms`, `s`, `m`, `h` and `d`, along with their usual long forms
+//! (`sec`, `second`, `seconds`, `min`, `minute`, `hr`, `hour`, `day`, ...).
+//!
+//! An optional nginx-style `burst=N` lets up to `N` requests over the rate wait for the
+//! quota instead of being rejected, see
+//! [`crate::config::RateLimitConfig::delay`].
+//!
+//! [`crate::config::RateLimitConfig::from_rate`] builds a configuration from a rate string.
+//!
+//! # Example
+//!
+//! ```rust
+//! use actix_web_ratelimit::rate::Rate;
+//! use std::time::Duration;
+//!
+//! let rate: Rate = "5r/s burst=10".parse()?;
+//! assert_eq!(rate.max_requests, 5);
+//! assert_eq!(rate.window, Duration::from_secs(1));
+//! assert_eq!(rate.burst, Some(10));
+//!
+//! let err = "100/fortnight".parse::<Rate>().unwrap_err();
+//! assert_eq!(err.to_string(), "invalid rate \"100/fortnight\": unknown unit \"fortnight\"");
+//! # Ok::<(), actix_web_ratelimit::rate::RateParseError>(())
+//! ```
+use std::{fmt, str::FromStr, time::Duration};
+
+/// Limit described by a rate string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate {
+    /// Maximum number of requests within the window
+    pub max_requests: usize,
+    /// Duration of the window
+    pub window: Duration,
+    /// Number of requests over the rate allowed to wait for the quota
+    pub burst: Option<usize>,
+}
+
+/// Error returned when a rate string cannot be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateParseError {
+    input: String,
+    kind: ErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ErrorKind {
+    Empty,
+    MissingSeparator,
+    InvalidCount(String),
+    ZeroCount,
+    UnknownUnit(String),
+    ZeroWindow,
+    InvalidOption(String),
+}
+
+impl fmt::Display for RateParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid rate {:?}: ", self.input)?;
+        match &self.kind {
+            ErrorKind::Empty => write!(f, "empty string"),
+            ErrorKind::MissingSeparator => {
+                write!(f, "expected `<count>/<unit>` or `<count> per <unit>`")
+            }
+            ErrorKind::InvalidCount(count) => write!(f, "invalid count {:?}", count),
+            ErrorKind::ZeroCount => write!(f, "count must be greater than zero"),
+            ErrorKind::UnknownUnit(unit) => write!(f, "unknown unit {:?}", unit),
+            ErrorKind::ZeroWindow => write!(f, "window must be greater than zero"),
+            ErrorKind::InvalidOption(option) => {
+                write!(f, "invalid option {:?}, expected `burst=<count>`", option)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RateParseError {}
+
+impl FromStr for Rate {
+    type Err = RateParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fail = |kind| RateParseError {
+            input: s.to_string(),
+            kind,
+        };
+
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(fail(ErrorKind::Empty));
+        }
+
+        let (count, rest) = s
+            .split_once('/')
+            .or_else(|| s.split_once(" per "))
+            .ok_or_else(|| fail(ErrorKind::MissingSeparator))?;
+        let count = count.trim();
+        // nginx writes rates as `5r/s`
+        let digits = count.strip_suffix('r').unwrap_or(count).trim_end();
+        let max_requests: usize = digits
+            .parse()
+            .map_err(|_| fail(ErrorKind::InvalidCount(count.to_string())))?;
+        if max_requests == 0 {
+            return Err(fail(ErrorKind::ZeroCount));
+        }
+
+        // The unit, possibly preceded by a count, is followed by options such as `burst=10`
+        let mut words = rest.split_whitespace();
+        let mut unit = words.next().unwrap_or_default().to_string();
+        if unit.bytes().all(|b| b.is_ascii_digit())
+            && let Some(word) = words.next()
+        {
+            unit.push_str(word);
+        }
+        let window = parse_window(&unit).ok_or_else(|| fail(ErrorKind::UnknownUnit(unit)))?;
+        if window.is_zero() {
+            return Err(fail(ErrorKind::ZeroWindow));
+        }
+
+        let mut burst = None;
+        for option in words {
+            let value = option
+                .strip_prefix("burst=")
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| fail(ErrorKind::InvalidOption(option.to_string())))?;
+            burst = Some(value);
+        }
+
+        Ok(Rate {
+            max_requests,
+            window,
+            burst,
+        })
+    }
+}
+
+/// Parses a window such as `s`, `minute` or `500ms`.
+fn parse_window(window: &str) -> Option<Duration> {
+    let split = window
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(window.len());
+    let (count, unit) = window.split_at(split);
+    let count: u32 = match count {
+        "" => 1,
+        count => count.parse().ok()?,
+    };
+    let unit = match unit.trim() {
+        "ms" | "msec" | "millisecond" | "milliseconds" => Duration::from_millis(1),
+        "s" | "sec" | "secs" | "second" | "seconds" => Duration::from_secs(1),
+        "m" | "min" | "mins" | "minute" | "minutes" => Duration::from_secs(60),
+        "h" | "hr" | "hrs" | "hour" | "hours" => Duration::from_secs(60 * 60),
+        "d" | "day" | "days" => Duration::from_secs(24 * 60 * 60),
+        _ => return None,
+    };
+    unit.checked_mul(count)
+}
@@ -0,0 +1,400 @@
+use futures_channel::oneshot;
+use log::error;
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, OnceLock,
+        mpsc::{Receiver, RecvTimeoutError, SyncSender, TrySendError, sync_channel},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::RateLimitConfig,
+    store::{RateLimitState, RateLimitStore, StoreError, StoreFuture, StoreResult},
+};
+
+/// Maximum number of checks sent together by default
+const DEFAULT_MAX_BATCH: usize = 64;
+/// Time the first check of a batch waits for others by default
+const DEFAULT_MAX_WAIT: Duration = Duration::from_millis(1);
+/// Number of checks that can wait for the batching thread; once full, blocking callers
+/// wait and the middleware checks with the store directly
+const QUEUE_CAPACITY: usize = 4096;
+
+/// Hands the result of a check back to its caller.
+type Reply = Box<dyn FnOnce(StoreResult<RateLimitState>) + Send>;
+
+/// Check waiting for the batching thread.
+struct Job {
+    key: String,
+    cost: usize,
+    max_requests: usize,
+    window: Duration,
+    reply: Reply,
+}
+
+/// Wrapper collecting the checks of concurrent requests and sending them to the store
+/// together through [`RateLimitStore::check_batch`].
+///
+/// At high request rates, each request otherwise pays for its own round trip. The
+/// batching thread waits up to `max_wait` after the first check for more to arrive, or
+/// until `max_batch` checks are collected, then sends them in one call, e.g. a single
+/// pipeline with [`crate::store::RedisStore`], and hands each result back to its
+/// request.
+///
+/// The middleware awaits the result through [`RateLimitStore::check_all_async`], so a
+/// worker keeps serving requests while its checks wait and a batch can hold many
+/// requests of the same worker. Direct calls to [`RateLimitStore::check`] are batched
+/// too, but block the calling thread until the batch is sent. Only checks of a single
+/// limit are batched: checks of several, e.g. with a [`crate::config::GlobalLimit`],
+/// and every other call go to the store directly. Checks reach the store with their
+/// `max_requests` and `window_secs` only.
+///
+/// # Example
+///
+/// ```rust
+/// use actix_web_ratelimit::config::RateLimitConfig;
+/// use actix_web_ratelimit::store::{BatchingStore, MemoryStore, RateLimitStore};
+/// use std::time::Duration;
+///
+/// // A MemoryStore stands in for Redis here
+/// let store = BatchingStore::new(MemoryStore::new())
+///     .max_batch(32)
+///     .max_wait(Duration::from_micros(500));
+/// let config = RateLimitConfig::default().max_requests(1);
+///
+/// assert!(!store.check("client", 1, &config).unwrap().limited);
+/// assert!(store.check("client", 1, &config).unwrap().limited);
+/// ```
+pub struct BatchingStore<S> {
+    store: Arc<S>,
+    /// Maximum number of checks sent together
+    max_batch: usize,
+    /// Time the first check of a batch waits for others
+    max_wait: Duration,
+    /// Queue of the batching thread, started on first use
+    sender: OnceLock<Option<SyncSender<Job>>>,
+}
+
+impl<S: RateLimitStore + 'static> BatchingStore<S> {
+    /// Wraps `store`, sending up to 64 checks together after waiting at most 1
+    /// millisecond.
+    pub fn new(store: S) -> Self {
+        Self {
+            store: Arc::new(store),
+            max_batch: DEFAULT_MAX_BATCH,
+            max_wait: DEFAULT_MAX_WAIT,
+            sender: OnceLock::new(),
+        }
+    }
+
+    /// Sets the maximum number of checks sent together, at least 1.
+    pub fn max_batch(mut self, max_batch: usize) -> Self {
+        self.max_batch = max_batch.max(1);
+        self
+    }
+
+    /// Sets how long the first check of a batch waits for others to arrive.
+    pub fn max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = max_wait;
+        self
+    }
+
+    /// Returns the queue of the batching thread, starting it on first use so that
+    /// builder calls have been applied.
+    fn sender(&self) -> Option<&SyncSender<Job>> {
+        self.sender
+            .get_or_init(|| {
+                let (sender, receiver) = sync_channel(QUEUE_CAPACITY);
+                let store = self.store.clone();
+                let (max_batch, max_wait) = (self.max_batch, self.max_wait);
+                let spawned = std::thread::Builder::new()
+                    .name("ratelimit-batch".to_string())
+                    .spawn(move || run(store.as_ref(), receiver, max_batch, max_wait));
+                match spawned {
+                    Ok(_) => Some(sender),
+                    Err(err) => {
+                        error!("Failed to start rate limit batching thread: {}", err);
+                        None
+                    }
+                }
+            })
+            .as_ref()
+    }
+
+    /// Queues a check for the next batch, or returns `false` if the batching thread is
+    /// not running, or if the queue is full and `block` is not set.
+    fn queue(
+        &self,
+        key: &str,
+        cost: usize,
+        config: &RateLimitConfig,
+        reply: Reply,
+        block: bool,
+    ) -> StoreResult<bool> {
+        let Some(sender) = self.sender() else {
+            return Ok(false);
+        };
+        let job = Job {
+            key: key.to_string(),
+            cost,
+            max_requests: config.max_requests,
+            window: config.window_secs,
+            reply,
+        };
+        if block {
+            sender.send(job).map_err(|_| stopped())?;
+            return Ok(true);
+        }
+        match sender.try_send(job) {
+            Ok(()) => Ok(true),
+            Err(TrySendError::Full(_)) => Ok(false),
+            Err(TrySendError::Disconnected(_)) => Err(stopped()),
+        }
+    }
+}
+
+/// Error returned to the checks of a batching thread that has stopped.
+fn stopped() -> StoreError {
+    StoreError::Other("rate limit batching thread has stopped".to_string())
+}
+
+/// Collects checks into batches until the store is dropped.
+fn run<S: RateLimitStore>(
+    store: &S,
+    receiver: Receiver<Job>,
+    max_batch: usize,
+    max_wait: Duration,
+) {
+    // Configurations carrying the limits checks are made with
+    let mut configs: HashMap<(usize, Duration), RateLimitConfig> = HashMap::new();
+
+    while let Ok(first) = receiver.recv() {
+        let deadline = Instant::now() + max_wait;
+        let mut batch = vec![first];
+        while batch.len() < max_batch {
+            match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(job) => batch.push(job),
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        for job in &batch {
+            configs
+                .entry((job.max_requests, job.window))
+                .or_insert_with(|| {
+                    RateLimitConfig::default()
+                        .max_requests(job.max_requests)
+                        .window(job.window)
+                });
+        }
+        let checks: Vec<_> = batch
+            .iter()
+            .map(|job| {
+                let config = &configs[&(job.max_requests, job.window)];
+                (job.key.as_str(), job.cost, config)
+            })
+            .collect();
+        let results = store.check_batch(&checks);
+
+        for (job, result) in batch.into_iter().zip(results) {
+            (job.reply)(result);
+        }
+    }
+}
+
+impl<S: RateLimitStore + 'static> RateLimitStore for BatchingStore<S> {
+    /// Queues the check for the next batch and blocks until its result is back.
+    fn check(
+        &self,
+        key: &str,
+        cost: usize,
+        config: &RateLimitConfig,
+    ) -> StoreResult<RateLimitState> {
+        let (reply, result) = sync_channel(1);
+        let reply: Reply = Box::new(move |state| {
+            // The request is gone if its caller stopped waiting
+            let _ = reply.send(state);
+        });
+        if !self.queue(key, cost, config, reply, true)? {
+            return self.store.check(key, cost, config);
+        }
+        result.recv().map_err(|_| stopped())?
+    }
+
+    fn backend(&self) -> &'static str {
+        self.store.backend()
+    }
+
+    fn peek(
+        &self,
+        key: &str,
+        cost: usize,
+        config: &RateLimitConfig,
+    ) -> StoreResult<RateLimitState> {
+        self.store.peek(key, cost, config)
+    }
+
+    fn refund(&self, key: &str, cost: usize, config: &RateLimitConfig) -> StoreResult<()> {
+        self.store.refund(key, cost, config)
+    }
+
+    fn check_all(
+        &self,
+        checks: &[(&str, &RateLimitConfig)],
+        cost: usize,
+    ) -> StoreResult<RateLimitState> {
+        self.store.check_all(checks, cost)
+    }
+
+    /// Queues the check of a single limit for the next batch, returning a future
+    /// resolving once the batch is sent.
+    ///
+    /// Never waits for room in the queue: if it is full, the check goes to the store
+    /// directly.
+    fn check_all_async(
+        &self,
+        checks: &[(&str, &RateLimitConfig)],
+        cost: usize,
+    ) -> StoreFuture<RateLimitState> {
+        let &[(key, config)] = checks else {
+            return Box::pin(std::future::ready(self.store.check_all(checks, cost)));
+        };
+        let (reply, result) = oneshot::channel();
+        let reply: Reply = Box::new(move |state| {
+            // The request is gone if its caller stopped waiting
+            let _ = reply.send(state);
+        });
+        match self.queue(key, cost, config, reply, false) {
+            Ok(true) => Box::pin(async move { result.await.map_err(|_| stopped())? }),
+            Ok(false) => Box::pin(std::future::ready(self.store.check(key, cost, config))),
+            Err(err) => Box::pin(std::future::ready(Err(err))),
+        }
+    }
+
+    fn check_batch(
+        &self,
+        checks: &[(&str, usize, &RateLimitConfig)],
+    ) -> Vec<StoreResult<RateLimitState>> {
+        self.store.check_batch(checks)
+    }
+
+    fn reset(&self, key: &str) -> StoreResult<()> {
+        self.store.reset(key)
+    }
+
+    fn ban(&self, key: &str, duration: Option<Duration>) -> StoreResult<()> {
+        self.store.ban(key, duration)
+    }
+
+    fn unban(&self, key: &str) -> StoreResult<()> {
+        self.store.unban(key)
+    }
+
+    fn is_banned(&self, key: &str) -> StoreResult<bool> {
+        self.store.is_banned(key)
+    }
+
+    fn reset_prefix(&self, prefix: &str) -> StoreResult<usize> {
+        self.store.reset_prefix(prefix)
+    }
+
+    fn reset_all(&self) -> StoreResult<usize> {
+        self.store.reset_all()
+    }
+
+    fn snapshot(&self, config: &RateLimitConfig) -> StoreResult<Vec<(String, usize)>> {
+        self.store.snapshot(config)
+    }
+
+    fn top_keys(
+        &self,
+        limit: usize,
+        config: &RateLimitConfig,
+    ) -> StoreResult<Vec<(String, usize)>> {
+        self.store.top_keys(limit, config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use std::sync::{
+        Barrier,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    /// Store holding its first batch until the test lets it through, counting the
+    /// checks it gets directly.
+    struct Stalled {
+        store: MemoryStore,
+        direct: AtomicUsize,
+        started: Barrier,
+        resumed: Barrier,
+        stalled: AtomicUsize,
+    }
+
+    impl RateLimitStore for Stalled {
+        fn check(
+            &self,
+            key: &str,
+            cost: usize,
+            config: &RateLimitConfig,
+        ) -> StoreResult<RateLimitState> {
+            self.direct.fetch_add(1, Ordering::SeqCst);
+            self.store.check(key, cost, config)
+        }
+
+        fn check_all(
+            &self,
+            checks: &[(&str, &RateLimitConfig)],
+            cost: usize,
+        ) -> StoreResult<RateLimitState> {
+            self.store.check_all(checks, cost)
+        }
+
+        fn check_batch(
+            &self,
+            checks: &[(&str, usize, &RateLimitConfig)],
+        ) -> Vec<StoreResult<RateLimitState>> {
+            if self.stalled.fetch_add(1, Ordering::SeqCst) == 0 {
+                self.started.wait();
+                self.resumed.wait();
+            }
+            self.store.check_batch(checks)
+        }
+    }
+
+    #[actix_web::test]
+    async fn full_queue_does_not_block_async_checks() {
+        let store = Arc::new(Stalled {
+            store: MemoryStore::new(),
+            direct: AtomicUsize::new(0),
+            started: Barrier::new(2),
+            resumed: Barrier::new(2),
+            stalled: AtomicUsize::new(0),
+        });
+        let batching = BatchingStore::new(store.clone()).max_batch(1);
+        let config = RateLimitConfig::default().max_requests(usize::MAX);
+
+        // The first check holds the batching thread, the next ones fill the queue
+        let first = batching.check_all_async(&[("client", &config)], 1);
+        store.started.wait();
+        let queued: Vec<_> = (0..QUEUE_CAPACITY)
+            .map(|_| batching.check_all_async(&[("client", &config)], 1))
+            .collect();
+        assert_eq!(store.direct.load(Ordering::SeqCst), 0);
+
+        // Returns right away, checked with the store directly
+        let overflow = batching.check_all_async(&[("client", &config)], 1);
+        assert_eq!(store.direct.load(Ordering::SeqCst), 1);
+
+        store.resumed.wait();
+        let checks = std::iter::once(first).chain(queued).chain([overflow]);
+        for result in futures_util::future::join_all(checks).await {
+            assert!(!result.unwrap().limited);
+        }
+    }
+}
@@ -0,0 +1,468 @@
+use dashmap::DashMap;
+use log::{debug, info, warn};
+use std::{
+    sync::{
+        Mutex, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::RateLimitConfig,
+    store::{RateLimitState, RateLimitStore, StoreError, StoreResult},
+};
+
+/// Number of consecutive errors opening the circuit by default
+const DEFAULT_FAILURE_THRESHOLD: usize = 5;
+/// Time the backend is skipped for once the circuit opens by default
+const DEFAULT_COOL_DOWN: Duration = Duration::from_secs(30);
+/// Number of successful probes closing the circuit by default
+const DEFAULT_HALF_OPEN_PROBES: usize = 1;
+/// Number of keys counted by the fallback tracked before expired ones are first pruned
+const MIN_PRUNE_AT: usize = 1024;
+
+/// State of a [`CircuitBreaker`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// The backend is healthy and handles every call
+    Closed,
+    /// The backend failed repeatedly and is skipped until the cool-down elapses
+    Open,
+    /// The cool-down elapsed and a few probe calls test whether the backend recovered
+    HalfOpen,
+}
+
+/// Bookkeeping of the circuit, guarded by a mutex.
+enum Circuit {
+    Closed { failures: usize },
+    Open { until: Instant },
+    HalfOpen { probes: usize, successes: usize },
+}
+
+/// Wrapper around any [`RateLimitStore`] that stops calling it while it is failing.
+///
+/// When a remote backend such as Redis is degraded, every request would otherwise pay
+/// for a connection attempt and a timeout. The breaker counts consecutive errors and,
+/// once `failure_threshold` is reached, opens: the backend is skipped for `cool_down`.
+/// Checks, peeks and ban lookups are then served by the fallback store if one is set,
+/// or fail with [`StoreError::CircuitOpen`], which the middleware handles like any store
+/// error, see [`RateLimitConfig::fail_open`], without logging it on every request.
+/// Writes, such as bans and resets, are never sent to the fallback: they would only
+/// change its copy of the state, so they fail with [`StoreError::CircuitOpen`]. Refunds
+/// go to the store that last counted the key, until its window ends.
+/// Only the opening and closing of the circuit are logged.
+///
+/// After the cool-down the circuit is half-open: up to `half_open_probes` calls reach
+/// the backend again. The circuit closes once all of them succeed, and opens for
+/// another cool-down as soon as one fails.
+///
+/// [`StoreError::Unsupported`] is not a failure of the backend and is never counted.
+///
+/// # Example
+///
+/// ```rust
+/// use actix_web_ratelimit::config::RateLimitConfig;
+/// use actix_web_ratelimit::store::{
+///     CircuitBreaker, CircuitState, MemoryStore, RateLimitState, RateLimitStore, StoreError,
+///     StoreResult,
+/// };
+/// use std::time::Duration;
+///
+/// // A backend that is down
+/// struct Unreachable;
+///
+/// impl RateLimitStore for Unreachable {
+///     fn check(&self, _: &str, _: usize, _: &RateLimitConfig) -> StoreResult<RateLimitState> {
+///         Err(StoreError::Other("connection refused".to_string()))
+///     }
+/// }
+///
+/// let store = CircuitBreaker::new(Unreachable)
+///     .failure_threshold(2)
+///     .cool_down(Duration::from_secs(10))
+///     .fallback(MemoryStore::new());
+/// let config = RateLimitConfig::default().max_requests(1);
+///
+/// // Errors are answered by the fallback, the second one opens the circuit
+/// assert!(!store.check("client", 1, &config)?.limited);
+/// assert!(store.check("client", 1, &config)?.limited);
+/// assert_eq!(store.state(), CircuitState::Open);
+///
+/// // Writes are not sent to the fallback
+/// assert!(matches!(store.ban("client", None), Err(StoreError::CircuitOpen)));
+/// assert!(!store.is_banned("client")?);
+/// # Ok::<(), StoreError>(())
+/// ```
+pub struct CircuitBreaker<S> {
+    /// Store protected by the breaker
+    store: S,
+    /// Store serving calls while the backend is skipped or failing
+    fallback: Option<Box<dyn RateLimitStore>>,
+    /// Number of consecutive errors opening the circuit
+    failure_threshold: usize,
+    /// Time the backend is skipped for once the circuit opens
+    cool_down: Duration,
+    /// Number of successful probes closing the circuit
+    half_open_probes: usize,
+    circuit: Mutex<Circuit>,
+    /// Keys last counted by the fallback, with when their window ends, so that refunds
+    /// reach the store that counted them
+    fallback_counted: DashMap<String, Instant>,
+    /// Number of tracked keys at which expired ones are pruned next
+    prune_at: AtomicUsize,
+}
+
+impl<S: RateLimitStore> CircuitBreaker<S> {
+    /// Wraps `store`, opening after 5 consecutive errors for a 30 seconds cool-down and
+    /// closing after 1 successful probe.
+    pub fn new(store: S) -> Self {
+        Self {
+            store,
+            fallback: None,
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+            cool_down: DEFAULT_COOL_DOWN,
+            half_open_probes: DEFAULT_HALF_OPEN_PROBES,
+            circuit: Mutex::new(Circuit::Closed { failures: 0 }),
+            fallback_counted: DashMap::new(),
+            prune_at: AtomicUsize::new(MIN_PRUNE_AT),
+        }
+    }
+
+    /// Sets the number of consecutive errors opening the circuit, at least 1.
+    pub fn failure_threshold(mut self, failures: usize) -> Self {
+        self.failure_threshold = failures.max(1);
+        self
+    }
+
+    /// Sets how long the backend is skipped for once the circuit opens.
+    pub fn cool_down(mut self, cool_down: Duration) -> Self {
+        self.cool_down = cool_down;
+        self
+    }
+
+    /// Sets the number of probe calls that must succeed to close the circuit, at least 1.
+    pub fn half_open_probes(mut self, probes: usize) -> Self {
+        self.half_open_probes = probes.max(1);
+        self
+    }
+
+    /// Serves checks, peeks and ban lookups from `fallback` while the circuit is open or
+    /// the backend fails, typically a [`crate::store::MemoryStore`] enforcing the limits
+    /// per instance.
+    pub fn fallback<F: RateLimitStore + 'static>(mut self, fallback: F) -> Self {
+        self.fallback = Some(Box::new(fallback));
+        self
+    }
+
+    /// Returns the current state of the circuit.
+    pub fn state(&self) -> CircuitState {
+        match *self.lock() {
+            Circuit::Closed { .. } => CircuitState::Closed,
+            Circuit::Open { until } if Instant::now() < until => CircuitState::Open,
+            Circuit::Open { .. } | Circuit::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Circuit> {
+        self.circuit.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns whether a call may reach the backend, taking a probe slot if half-open.
+    fn allow(&self) -> bool {
+        let mut circuit = self.lock();
+        match *circuit {
+            Circuit::Closed { .. } => true,
+            Circuit::Open { until } if Instant::now() < until => false,
+            Circuit::Open { .. } => {
+                *circuit = Circuit::HalfOpen {
+                    probes: 1,
+                    successes: 0,
+                };
+                true
+            }
+            Circuit::HalfOpen { ref mut probes, .. } if *probes < self.half_open_probes => {
+                *probes += 1;
+                true
+            }
+            Circuit::HalfOpen { .. } => false,
+        }
+    }
+
+    /// Updates the circuit with the outcome of a call made to the backend.
+    fn record<T>(&self, result: &StoreResult<T>) {
+        let mut circuit = self.lock();
+        match (result, &mut *circuit) {
+            // Not a failure of the backend, give the probe slot back
+            (Err(StoreError::Unsupported(_)), Circuit::HalfOpen { probes, .. }) => {
+                *probes = probes.saturating_sub(1)
+            }
+            (Err(StoreError::Unsupported(_)), _) => {}
+            (Ok(_), Circuit::Closed { failures }) => *failures = 0,
+            (Ok(_), Circuit::HalfOpen { successes, .. }) => {
+                *successes += 1;
+                if *successes >= self.half_open_probes {
+                    info!(
+                        "Rate limit store {} recovered, closing circuit",
+                        self.store.backend()
+                    );
+                    *circuit = Circuit::Closed { failures: 0 };
+                }
+            }
+            (Err(_), Circuit::Closed { failures }) => {
+                *failures += 1;
+                if *failures >= self.failure_threshold {
+                    warn!(
+                        "Rate limit store {} failed {} times in a row, opening circuit for {:?}",
+                        self.store.backend(),
+                        failures,
+                        self.cool_down
+                    );
+                    *circuit = Circuit::Open {
+                        until: Instant::now() + self.cool_down,
+                    };
+                }
+            }
+            (Err(_), Circuit::HalfOpen { .. }) => {
+                warn!(
+                    "Rate limit store {} still failing, opening circuit for {:?}",
+                    self.store.backend(),
+                    self.cool_down
+                );
+                *circuit = Circuit::Open {
+                    until: Instant::now() + self.cool_down,
+                };
+            }
+            // A call started before the circuit opened
+            (_, Circuit::Open { .. }) => {}
+        }
+    }
+
+    /// Runs `op` on the backend if the circuit lets it through, or on the fallback.
+    fn call<T>(&self, op: impl Fn(&dyn RateLimitStore) -> StoreResult<T>) -> StoreResult<T> {
+        self.serve(op).0
+    }
+
+    /// Like [`CircuitBreaker::call`], also returning whether the fallback served the call.
+    fn serve<T>(
+        &self,
+        op: impl Fn(&dyn RateLimitStore) -> StoreResult<T>,
+    ) -> (StoreResult<T>, bool) {
+        match (self.call_backend(&op), &self.fallback) {
+            (Err(err), Some(fallback)) if !matches!(err, StoreError::Unsupported(_)) => {
+                debug!("Rate limit store unavailable, using fallback: {}", err);
+                (op(fallback.as_ref()), true)
+            }
+            (result, _) => (result, false),
+        }
+    }
+
+    /// Counts requests of `keys` with `op`, remembering which store counted them.
+    fn count(
+        &self,
+        keys: &[&str],
+        op: impl Fn(&dyn RateLimitStore) -> StoreResult<RateLimitState>,
+    ) -> StoreResult<RateLimitState> {
+        let (result, fallback) = self.serve(op);
+        match &result {
+            Ok(state) if fallback => {
+                let now = Instant::now();
+                for key in keys {
+                    self.fallback_counted
+                        .insert(key.to_string(), now + state.reset_after);
+                }
+                self.prune(now);
+            }
+            Ok(_) => {
+                for key in keys {
+                    self.fallback_counted.remove(*key);
+                }
+            }
+            Err(_) => {}
+        }
+        result
+    }
+
+    /// Drops the keys whose window has ended once there are too many of them.
+    fn prune(&self, now: Instant) {
+        if self.fallback_counted.len() < self.prune_at.load(Ordering::Relaxed) {
+            return;
+        }
+        self.fallback_counted.retain(|_, until| *until > now);
+        self.prune_at.store(
+            (self.fallback_counted.len() * 2).max(MIN_PRUNE_AT),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Runs `op` on the backend if the circuit lets it through, never on the fallback.
+    fn call_backend<T>(
+        &self,
+        op: impl Fn(&dyn RateLimitStore) -> StoreResult<T>,
+    ) -> StoreResult<T> {
+        if !self.allow() {
+            return Err(StoreError::CircuitOpen);
+        }
+        let result = op(&self.store);
+        self.record(&result);
+        result
+    }
+}
+
+impl<S: RateLimitStore> RateLimitStore for CircuitBreaker<S> {
+    fn check(
+        &self,
+        key: &str,
+        cost: usize,
+        config: &RateLimitConfig,
+    ) -> StoreResult<RateLimitState> {
+        self.count(&[key], |store| store.check(key, cost, config))
+    }
+
+    fn backend(&self) -> &'static str {
+        self.store.backend()
+    }
+
+    fn peek(
+        &self,
+        key: &str,
+        cost: usize,
+        config: &RateLimitConfig,
+    ) -> StoreResult<RateLimitState> {
+        self.call(|store| store.peek(key, cost, config))
+    }
+
+    fn refund(&self, key: &str, cost: usize, config: &RateLimitConfig) -> StoreResult<()> {
+        let counted_by_fallback = self
+            .fallback_counted
+            .get(key)
+            .is_some_and(|until| *until > Instant::now());
+        match &self.fallback {
+            Some(fallback) if counted_by_fallback => fallback.refund(key, cost, config),
+            _ => self.call_backend(|store| store.refund(key, cost, config)),
+        }
+    }
+
+    fn check_all(
+        &self,
+        checks: &[(&str, &RateLimitConfig)],
+        cost: usize,
+    ) -> StoreResult<RateLimitState> {
+        let keys = checks.iter().map(|(key, _)| *key).collect::<Vec<_>>();
+        self.count(&keys, |store| store.check_all(checks, cost))
+    }
+
+    fn reset(&self, key: &str) -> StoreResult<()> {
+        self.call_backend(|store| store.reset(key))
+    }
+
+    fn ban(&self, key: &str, duration: Option<Duration>) -> StoreResult<()> {
+        self.call_backend(|store| store.ban(key, duration))
+    }
+
+    fn unban(&self, key: &str) -> StoreResult<()> {
+        self.call_backend(|store| store.unban(key))
+    }
+
+    fn is_banned(&self, key: &str) -> StoreResult<bool> {
+        self.call(|store| store.is_banned(key))
+    }
+
+    fn reset_prefix(&self, prefix: &str) -> StoreResult<usize> {
+        self.call_backend(|store| store.reset_prefix(prefix))
+    }
+
+    fn reset_all(&self) -> StoreResult<usize> {
+        self.call_backend(|store| store.reset_all())
+    }
+
+    fn snapshot(&self, config: &RateLimitConfig) -> StoreResult<Vec<(String, usize)>> {
+        self.call_backend(|store| store.snapshot(config))
+    }
+
+    fn top_keys(
+        &self,
+        limit: usize,
+        config: &RateLimitConfig,
+    ) -> StoreResult<Vec<(String, usize)>> {
+        self.call_backend(|store| store.top_keys(limit, config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use std::sync::{Arc, atomic::AtomicBool};
+
+    /// Memory store that fails while `down` is set.
+    struct Flaky {
+        store: MemoryStore,
+        down: Arc<AtomicBool>,
+    }
+
+    impl Flaky {
+        fn up(&self) -> StoreResult<()> {
+            if self.down.load(Ordering::Relaxed) {
+                return Err(StoreError::Other("connection refused".to_string()));
+            }
+            Ok(())
+        }
+    }
+
+    impl RateLimitStore for Flaky {
+        fn check(
+            &self,
+            key: &str,
+            cost: usize,
+            config: &RateLimitConfig,
+        ) -> StoreResult<RateLimitState> {
+            self.up()?;
+            self.store.check(key, cost, config)
+        }
+
+        fn peek(
+            &self,
+            key: &str,
+            cost: usize,
+            config: &RateLimitConfig,
+        ) -> StoreResult<RateLimitState> {
+            self.up()?;
+            self.store.peek(key, cost, config)
+        }
+
+        fn refund(&self, key: &str, cost: usize, config: &RateLimitConfig) -> StoreResult<()> {
+            self.up()?;
+            self.store.refund(key, cost, config)
+        }
+    }
+
+    #[test]
+    fn refunds_reach_the_store_that_counted() {
+        let down = Arc::new(AtomicBool::new(false));
+        let store = CircuitBreaker::new(Flaky {
+            store: MemoryStore::new(),
+            down: down.clone(),
+        })
+        .failure_threshold(10)
+        .fallback(MemoryStore::new());
+        let config = RateLimitConfig::default().max_requests(2);
+
+        store.check("backend", 1, &config).unwrap();
+        down.store(true, Ordering::Relaxed);
+        store.check("fallback", 2, &config).unwrap();
+        assert!(store.check("fallback", 1, &config).unwrap().limited);
+
+        // Served by the fallback although the backend is back
+        down.store(false, Ordering::Relaxed);
+        store.refund("fallback", 2, &config).unwrap();
+        let fallback = store.fallback.as_ref().unwrap();
+        assert_eq!(fallback.peek("fallback", 0, &config).unwrap().used, 0);
+
+        store.refund("backend", 1, &config).unwrap();
+        assert_eq!(
+            store.store.store.peek("backend", 0, &config).unwrap().used,
+            0
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use futures_util::future::BoxFuture;
+use std::fmt;
+
+/// Error returned by a [`crate::store::RateLimitStore`] when the backend cannot be consulted.
+///
+/// The middleware treats store errors as non-fatal by default: the request is allowed
+/// and the error is logged, so a broken backend never takes the whole service down. See
+/// [`crate::config::RateLimitConfig::fail_open`] to reject such requests instead.
+#[derive(Debug)]
+pub enum StoreError {
+    /// Error reported by the Redis client (requires `redis` feature)
+    #[cfg(feature = "redis")]
+    Redis(redis::RedisError),
+    /// I/O error talking to a memcached server (requires `memcached` feature)
+    #[cfg(feature = "memcached")]
+    Memcached(std::io::Error),
+    /// Error reported by SQLite (requires `sqlite` feature)
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+    /// Error reported by PostgreSQL (requires `postgres` feature)
+    #[cfg(feature = "postgres")]
+    Postgres(tokio_postgres::Error),
+    /// The store does not implement the named operation
+    Unsupported(&'static str),
+    /// The backend is skipped by an open [`crate::store::CircuitBreaker`]
+    CircuitOpen,
+    /// Any other backend failure, described by a message
+    Other(String),
+}
+
+/// Result type returned by [`crate::store::RateLimitStore`] operations.
+pub type StoreResult<T> = Result<T, StoreError>;
+
+/// Future resolving to the result of a [`crate::store::RateLimitStore`] operation, see
+/// [`crate::store::RateLimitStore::check_all_async`].
+pub type StoreFuture<T> = BoxFuture<'static, StoreResult<T>>;
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "redis")]
+            StoreError::Redis(err) => write!(f, "redis error: {}", err),
+            #[cfg(feature = "memcached")]
+            StoreError::Memcached(err) => write!(f, "memcached error: {}", err),
+            #[cfg(feature = "sqlite")]
+            StoreError::Sqlite(err) => write!(f, "sqlite error: {}", err),
+            #[cfg(feature = "postgres")]
+            StoreError::Postgres(err) => write!(f, "postgres error: {}", err),
+            StoreError::Unsupported(op) => write!(f, "operation not supported: {}", op),
+            StoreError::CircuitOpen => write!(f, "circuit breaker open"),
+            StoreError::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "redis")]
+            StoreError::Redis(err) => Some(err),
+            #[cfg(feature = "memcached")]
+            StoreError::Memcached(err) => Some(err),
+            #[cfg(feature = "sqlite")]
+            StoreError::Sqlite(err) => Some(err),
+            #[cfg(feature = "postgres")]
+            StoreError::Postgres(err) => Some(err),
+            StoreError::Unsupported(_) | StoreError::CircuitOpen | StoreError::Other(_) => None,
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+impl From<redis::RedisError> for StoreError {
+    fn from(err: redis::RedisError) -> Self {
+        StoreError::Redis(err)
+    }
+}
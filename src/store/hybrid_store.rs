@@ -0,0 +1,575 @@
+use dashmap::DashMap;
+use log::{error, warn};
+use std::{
+    sync::{
+        Arc, OnceLock, Weak,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::RateLimitConfig,
+    store::{RateLimitState, RateLimitStore, StoreError, StoreResult},
+};
+
+/// Maximum age of the usage the local decisions rely on by default
+const DEFAULT_MAX_STALENESS: Duration = Duration::from_millis(100);
+
+/// Local view of a client.
+struct Entry {
+    /// Limit of the client, kept to sync it in the background
+    max_requests: usize,
+    /// Window of the client, kept to sync it in the background
+    window: Duration,
+    /// Units used by all instances as of the last sync
+    remote_used: usize,
+    /// Units being sent to the remote store
+    in_transit: usize,
+    /// Units recorded here and not sent to the remote store yet
+    pending: usize,
+    /// Units recorded here within the window, for the local share
+    local: Vec<(Instant, usize)>,
+    last_seen: Instant,
+    /// Number telling this entry apart from one recreated for the same client after a
+    /// reset, so that a sync started before the reset leaves the new one alone
+    generation: u64,
+}
+
+impl Entry {
+    fn new(config: &RateLimitConfig, remote_used: usize, now: Instant, generation: u64) -> Self {
+        Self {
+            max_requests: config.max_requests,
+            window: config.window_secs,
+            remote_used,
+            in_transit: 0,
+            pending: 0,
+            local: Vec::new(),
+            last_seen: now,
+            generation,
+        }
+    }
+}
+
+/// Client sent to the remote store by a sync.
+struct Synced {
+    key: String,
+    /// Units sent, 0 if the usage is only read back
+    sent: usize,
+    config: RateLimitConfig,
+    /// Generation of the entry the units were taken from
+    generation: u64,
+}
+
+/// Ban status of a client as read from the remote store.
+struct Ban {
+    banned: bool,
+    read_at: Instant,
+    /// When the middleware last asked for it, to stop refreshing idle clients
+    asked_at: Instant,
+}
+
+/// State shared with the sync thread.
+struct Shared {
+    remote: Box<dyn RateLimitStore>,
+    entries: DashMap<String, Entry>,
+    bans: DashMap<String, Ban>,
+    /// Generation of the next entry created
+    generations: AtomicU64,
+}
+
+/// Two-tier [`RateLimitStore`] deciding from a local cache and syncing usage to a remote
+/// store, typically a [`crate::store::RedisStore`], in the background.
+///
+/// Every check is answered locally from the usage of all instances as of the last sync,
+/// plus the units recorded here since. Every `max_staleness`, a background thread sends
+/// the units recorded for all clients with [`RateLimitStore::check_batch`], reads back
+/// the usage of the other clients seen within their window with
+/// [`RateLimitStore::peek_batch`], and refreshes the ban status of the clients asked
+/// about with [`RateLimitStore::is_banned_batch`], so a tick costs at most three round
+/// trips with a backend pipelining batches. The remote store is only called inline the
+/// first time a client is seen, and for its ban status when no sync refreshed it lately.
+///
+/// Units the remote store rejects during a sync, because other instances used the
+/// quota in the meantime, are kept and sent again by the next syncs, so the requests
+/// let through here are still counted once quota frees up. Only units exceeding the
+/// whole limit, which could never fit, are dropped with a warning.
+///
+/// This trades accuracy for latency: between two syncs, every instance may let through
+/// requests the others have already used the quota for, so a limit can be exceeded by
+/// what the instances accept within `max_staleness`. Use
+/// [`HybridStore::local_share`] to bound each instance's share of the limit as well.
+///
+/// Units recorded since the last sync are lost if the process stops without calling
+/// [`HybridStore::flush`].
+///
+/// # Example
+///
+/// ```rust
+/// use actix_web_ratelimit::config::RateLimitConfig;
+/// use actix_web_ratelimit::store::{HybridStore, MemoryStore, RateLimitStore};
+/// use std::time::Duration;
+///
+/// // A MemoryStore stands in for Redis here
+/// let store = HybridStore::new(MemoryStore::new())
+///     .max_staleness(Duration::from_millis(50))
+///     // Four instances share the limit
+///     .local_share(0.25);
+/// let config = RateLimitConfig::default().max_requests(8);
+///
+/// assert!(!store.check("client", 1, &config).unwrap().limited);
+/// assert!(!store.check("client", 1, &config).unwrap().limited);
+/// // This instance used its share of the limit
+/// assert!(store.check("client", 1, &config).unwrap().limited);
+/// ```
+pub struct HybridStore {
+    shared: Arc<Shared>,
+    /// Maximum age of the usage the local decisions rely on
+    max_staleness: Duration,
+    /// Fraction of each limit this instance may use on its own
+    local_share: Option<f64>,
+    /// Set once the sync thread is started
+    syncer: OnceLock<()>,
+}
+
+impl HybridStore {
+    /// Creates a store caching `remote` locally, syncing every 100 milliseconds.
+    pub fn new<S: RateLimitStore + 'static>(remote: S) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                remote: Box::new(remote),
+                entries: DashMap::new(),
+                bans: DashMap::new(),
+                generations: AtomicU64::new(0),
+            }),
+            max_staleness: DEFAULT_MAX_STALENESS,
+            local_share: None,
+            syncer: OnceLock::new(),
+        }
+    }
+
+    /// Sets the interval between syncs, bounding how old the usage of other instances
+    /// and the ban status of clients may be.
+    pub fn max_staleness(mut self, max_staleness: Duration) -> Self {
+        self.max_staleness = max_staleness.max(Duration::from_millis(1));
+        self
+    }
+
+    /// Limits the units this instance may grant each client within the window to
+    /// `share` of the limit, rounded up, on top of the limit shared by all instances.
+    ///
+    /// With `n` instances behind a load balancer, a share of `1/n` keeps the limit from
+    /// being exceeded by much between syncs, at the cost of rejecting clients whose
+    /// requests are unevenly spread.
+    pub fn local_share(mut self, share: f64) -> Self {
+        self.local_share = Some(share.clamp(0.0, 1.0));
+        self
+    }
+
+    /// Sends the units recorded since the last sync to the remote store right away,
+    /// e.g. before shutting down.
+    pub fn flush(&self) {
+        self.shared.sync(self.max_staleness);
+    }
+
+    /// Starts the sync thread on first use, so that builder calls have been applied.
+    fn start_syncer(&self) {
+        self.syncer.get_or_init(|| {
+            let shared = Arc::downgrade(&self.shared);
+            let interval = self.max_staleness;
+            let spawned = std::thread::Builder::new()
+                .name("ratelimit-sync".to_string())
+                .spawn(move || Shared::run(shared, interval));
+            if let Err(err) = spawned {
+                error!("Failed to start rate limit sync thread: {}", err);
+            }
+        });
+    }
+
+    /// Computes the state of a client from its local view without recording anything.
+    fn evaluate(
+        &self,
+        entry: &mut Entry,
+        now: Instant,
+        cost: usize,
+        config: &RateLimitConfig,
+    ) -> RateLimitState {
+        entry.max_requests = config.max_requests;
+        entry.window = config.window_secs;
+        entry.last_seen = now;
+        entry
+            .local
+            .retain(|&(t, _)| now.duration_since(t) <= config.window_secs);
+
+        let used = entry
+            .remote_used
+            .saturating_add(entry.in_transit)
+            .saturating_add(entry.pending);
+        let mut limited = used.saturating_add(cost) > config.max_requests;
+        // The usage of other instances is known again after the next sync
+        let mut retry_after = self.max_staleness;
+
+        if let Some(share) = self.local_share {
+            let local_max = ((config.max_requests as f64 * share).ceil() as usize).max(1);
+            let local_used: usize = entry.local.iter().map(|&(_, c)| c).sum();
+            if local_used.saturating_add(cost) > local_max {
+                limited = true;
+                retry_after = entry.local.first().map_or(config.window_secs, |&(t, _)| {
+                    config
+                        .window_secs
+                        .saturating_sub(now.duration_since(t))
+                        .max(retry_after)
+                });
+            }
+        }
+
+        RateLimitState {
+            limited,
+            used,
+            remaining: config.max_requests.saturating_sub(used),
+            retry_after: if limited { retry_after } else { Duration::ZERO },
+            reset_after: config.window_secs,
+        }
+    }
+
+    /// Reads the usage of a client seen for the first time from the remote store.
+    fn remote_usage(&self, key: &str, config: &RateLimitConfig) -> usize {
+        match self.shared.remote.peek(key, 0, config) {
+            Ok(state) => state.used,
+            Err(StoreError::Unsupported(_)) => 0,
+            Err(err) => {
+                warn!("Rate limit store error for key({}): {}", key, err);
+                0
+            }
+        }
+    }
+}
+
+impl Shared {
+    /// Syncs every `interval` until the store is dropped.
+    fn run(shared: Weak<Shared>, interval: Duration) {
+        loop {
+            std::thread::sleep(interval);
+            match shared.upgrade() {
+                Some(shared) => shared.sync(interval),
+                None => break,
+            }
+        }
+    }
+
+    /// Sends pending units, reads back the usage of active clients and refreshes the
+    /// ban status of clients the middleware asked about, one batch each.
+    fn sync(&self, max_staleness: Duration) {
+        let now = Instant::now();
+        self.entries.retain(|_, entry| {
+            entry.pending > 0 || now.duration_since(entry.last_seen) <= entry.window
+        });
+        self.bans.retain(|key, ban| {
+            now.duration_since(ban.asked_at) <= max_staleness || self.entries.contains_key(key)
+        });
+
+        let (sends, peeks): (Vec<_>, Vec<_>) = self
+            .entries
+            .iter_mut()
+            .map(|mut entry| {
+                let sent = std::mem::take(&mut entry.pending);
+                entry.in_transit += sent;
+                Synced {
+                    key: entry.key().clone(),
+                    sent,
+                    config: RateLimitConfig::default()
+                        .max_requests(entry.max_requests)
+                        .window(entry.window),
+                    generation: entry.generation,
+                }
+            })
+            .partition(|synced| synced.sent > 0);
+
+        if !sends.is_empty() {
+            self.apply(&sends, &self.remote.check_batch(&borrowed(&sends)));
+        }
+        if !peeks.is_empty() {
+            self.apply(&peeks, &self.remote.peek_batch(&borrowed(&peeks)));
+        }
+        self.refresh_bans();
+    }
+
+    /// Updates the entries with the remote usage returned for `batch`, keeping the units
+    /// sent as pending if they were not recorded.
+    ///
+    /// Entries reset since the batch was taken are left alone: their units in transit
+    /// were never counted by the entry recreated in the meantime.
+    fn apply(&self, batch: &[Synced], results: &[StoreResult<RateLimitState>]) {
+        for (synced, result) in batch.iter().zip(results) {
+            let Synced {
+                key,
+                sent,
+                config,
+                generation,
+            } = synced;
+            let Some(mut entry) = self.entries.get_mut(key) else {
+                continue;
+            };
+            if entry.generation != *generation {
+                continue;
+            }
+            entry.in_transit = entry.in_transit.saturating_sub(*sent);
+            match result {
+                Ok(state) if *sent > 0 && state.limited => {
+                    // Excludes the units sent, which are counted again once they fit
+                    entry.remote_used = state.used;
+                    if *sent <= config.max_requests {
+                        entry.pending += sent;
+                    } else {
+                        warn!(
+                            "Rate limit store rejected {} units for key({}), dropping them",
+                            sent, key
+                        );
+                    }
+                }
+                Ok(state) => entry.remote_used = state.used,
+                Err(StoreError::Unsupported(_)) => entry.pending += sent,
+                Err(err) => {
+                    warn!("Rate limit store error for key({}): {}", key, err);
+                    entry.pending += sent;
+                }
+            }
+        }
+    }
+
+    /// Reads the ban status of the clients the middleware asked about.
+    fn refresh_bans(&self) {
+        let keys: Vec<String> = self.bans.iter().map(|ban| ban.key().clone()).collect();
+        if keys.is_empty() {
+            return;
+        }
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        let read_at = Instant::now();
+        for (key, result) in keys.iter().zip(self.remote.is_banned_batch(&keys)) {
+            match result {
+                Ok(banned) => {
+                    if let Some(mut ban) = self.bans.get_mut(*key) {
+                        ban.banned = banned;
+                        ban.read_at = read_at;
+                    }
+                }
+                Err(StoreError::Unsupported(_)) => {}
+                Err(err) => warn!("Rate limit store error for key({}): {}", key, err),
+            }
+        }
+    }
+}
+
+/// Borrows the keys and configurations of `batch` for a batch call to the remote store.
+fn borrowed(batch: &[Synced]) -> Vec<(&str, usize, &RateLimitConfig)> {
+    batch
+        .iter()
+        .map(|synced| (synced.key.as_str(), synced.sent, &synced.config))
+        .collect()
+}
+
+impl RateLimitStore for HybridStore {
+    /// Checks the client against its local view and records the request locally, to be
+    /// sent to the remote store by the next sync.
+    fn check(
+        &self,
+        key: &str,
+        cost: usize,
+        config: &RateLimitConfig,
+    ) -> StoreResult<RateLimitState> {
+        self.start_syncer();
+        let now = Instant::now();
+        let remote_used =
+            (!self.shared.entries.contains_key(key)).then(|| self.remote_usage(key, config));
+        let mut entry = self
+            .shared
+            .entries
+            .entry(key.to_string())
+            .or_insert_with(|| {
+                let generation = self.shared.generations.fetch_add(1, Ordering::Relaxed);
+                Entry::new(config, remote_used.unwrap_or(0), now, generation)
+            });
+
+        let mut state = self.evaluate(&mut entry, now, cost, config);
+        if state.limited {
+            warn!(
+                "Rate limit exceeded for key({}): used({}) + cost({}) > max_req({})",
+                key, state.used, cost, config.max_requests
+            );
+        } else {
+            entry.pending += cost;
+            if self.local_share.is_some() {
+                entry.local.push((now, cost));
+            }
+            state.used = state.used.saturating_add(cost);
+            state.remaining = state.remaining.saturating_sub(cost);
+        }
+        Ok(state)
+    }
+
+    fn backend(&self) -> &'static str {
+        "hybrid"
+    }
+
+    fn peek(
+        &self,
+        key: &str,
+        cost: usize,
+        config: &RateLimitConfig,
+    ) -> StoreResult<RateLimitState> {
+        let now = Instant::now();
+        match self.shared.entries.get_mut(key) {
+            Some(mut entry) => Ok(self.evaluate(&mut entry, now, cost, config)),
+            None => self.shared.remote.peek(key, cost, config),
+        }
+    }
+
+    /// Gives back units recorded locally and not synced yet.
+    fn refund(&self, key: &str, cost: usize, _config: &RateLimitConfig) -> StoreResult<()> {
+        if let Some(mut entry) = self.shared.entries.get_mut(key) {
+            entry.pending = entry.pending.saturating_sub(cost);
+            if let Some(i) = entry.local.iter().rposition(|&(_, c)| c == cost) {
+                entry.local.remove(i);
+            }
+        }
+        Ok(())
+    }
+
+    fn reset(&self, key: &str) -> StoreResult<()> {
+        self.shared.entries.remove(key);
+        self.shared.remote.reset(key)
+    }
+
+    fn ban(&self, key: &str, duration: Option<Duration>) -> StoreResult<()> {
+        self.shared.remote.ban(key, duration)?;
+        let now = Instant::now();
+        self.shared.bans.insert(
+            key.to_string(),
+            Ban {
+                banned: true,
+                read_at: now,
+                asked_at: now,
+            },
+        );
+        Ok(())
+    }
+
+    fn unban(&self, key: &str) -> StoreResult<()> {
+        self.shared.remote.unban(key)?;
+        self.shared.bans.remove(key);
+        Ok(())
+    }
+
+    /// Returns the ban status refreshed by the last sync, reading it from the remote
+    /// store only if no sync refreshed it within twice `max_staleness`, e.g. the first
+    /// time a client is seen.
+    fn is_banned(&self, key: &str) -> StoreResult<bool> {
+        self.start_syncer();
+        let now = Instant::now();
+        if let Some(mut ban) = self.shared.bans.get_mut(key)
+            && now.duration_since(ban.read_at) <= self.max_staleness * 2
+        {
+            ban.asked_at = now;
+            return Ok(ban.banned);
+        }
+        let banned = self.shared.remote.is_banned(key)?;
+        self.shared.bans.insert(
+            key.to_string(),
+            Ban {
+                banned,
+                read_at: now,
+                asked_at: now,
+            },
+        );
+        Ok(banned)
+    }
+
+    fn reset_prefix(&self, prefix: &str) -> StoreResult<usize> {
+        self.shared
+            .entries
+            .retain(|key, _| !key.starts_with(prefix));
+        self.shared.remote.reset_prefix(prefix)
+    }
+
+    fn snapshot(&self, config: &RateLimitConfig) -> StoreResult<Vec<(String, usize)>> {
+        self.shared.remote.snapshot(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use std::sync::{Barrier, atomic::AtomicBool};
+
+    /// Remote store holding the first batch of checks until the test lets it through.
+    struct Paused {
+        store: MemoryStore,
+        armed: AtomicBool,
+        started: Barrier,
+        resumed: Barrier,
+    }
+
+    impl RateLimitStore for Paused {
+        fn check(
+            &self,
+            key: &str,
+            cost: usize,
+            config: &RateLimitConfig,
+        ) -> StoreResult<RateLimitState> {
+            self.store.check(key, cost, config)
+        }
+
+        fn peek(
+            &self,
+            key: &str,
+            cost: usize,
+            config: &RateLimitConfig,
+        ) -> StoreResult<RateLimitState> {
+            self.store.peek(key, cost, config)
+        }
+
+        fn check_batch(
+            &self,
+            checks: &[(&str, usize, &RateLimitConfig)],
+        ) -> Vec<StoreResult<RateLimitState>> {
+            if self.armed.swap(false, Ordering::SeqCst) {
+                self.started.wait();
+                self.resumed.wait();
+            }
+            self.store.check_batch(checks)
+        }
+
+        fn reset(&self, key: &str) -> StoreResult<()> {
+            self.store.reset(key)
+        }
+    }
+
+    #[test]
+    fn reset_during_sync_leaves_the_new_entry_alone() {
+        let remote = Arc::new(Paused {
+            store: MemoryStore::new(),
+            armed: AtomicBool::new(true),
+            started: Barrier::new(2),
+            resumed: Barrier::new(2),
+        });
+        let store =
+            Arc::new(HybridStore::new(remote.clone()).max_staleness(Duration::from_secs(60)));
+        let config = RateLimitConfig::default().max_requests(3);
+
+        store.check("client", 2, &config).unwrap();
+        let syncing = {
+            let store = store.clone();
+            std::thread::spawn(move || store.flush())
+        };
+        remote.started.wait();
+        store.reset("client").unwrap();
+        store.check("client", 1, &config).unwrap();
+        remote.resumed.wait();
+        syncing.join().expect("sync thread panicked");
+
+        let state = store.peek("client", 0, &config).unwrap();
+        assert_eq!(state.used, 1);
+        assert!(!store.check("client", 1, &config).unwrap().limited);
+    }
+}
@@ -0,0 +1,758 @@
+#[cfg(feature = "memcached")]
+mod memcached_store_impl {
+    use crate::{
+        config::RateLimitConfig,
+        store::{RateLimitState, RateLimitStore, StoreError, StoreResult, now_ms},
+    };
+    use log::{debug, warn};
+    use std::{
+        io::{self, BufRead, BufReader, Read, Write},
+        net::{TcpStream, ToSocketAddrs},
+        sync::{Mutex, PoisonError},
+        time::Duration,
+    };
+
+    /// Default prefix for memcached keys used by the rate limiter
+    const MEMCACHED_PREFIX: &str = "rate_limit:";
+
+    /// Suffix of the keys marking a banned client
+    const BAN_SUFFIX: &str = ":ban";
+
+    /// Longest key memcached accepts
+    const MAX_KEY_LEN: usize = 250;
+
+    /// Number of attempts at a compare-and-swap before giving up
+    const MAX_CAS_ATTEMPTS: usize = 16;
+
+    /// Fractions of a token tracked by the token bucket
+    const TOKEN_SCALE: u64 = 1000;
+
+    /// Connections kept open per server
+    const MAX_IDLE_CONNECTIONS: usize = 16;
+
+    /// Longest expiry memcached reads as relative, 30 days; larger ones are Unix times
+    const MAX_RELATIVE_EXPTIME: u64 = 30 * 24 * 60 * 60;
+
+    /// Algorithm a [`MemcachedStore`] limits clients with.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum MemcachedAlgorithm {
+        /// Counts units in windows aligned to the wall clock with `add`/`incr`; at most
+        /// twice the limit may pass around the boundary of two windows
+        #[default]
+        FixedWindow,
+        /// Refills `max_requests` tokens per window continuously, updated with `gets`/`cas`
+        TokenBucket,
+    }
+
+    /// Memcached-based implementation of [`RateLimitStore`] (requires `memcached` feature).
+    ///
+    /// Memcached offers no sorted sets or scripts, so instead of the sliding window used
+    /// by the other stores it limits clients with a [`MemcachedAlgorithm`]:
+    ///
+    /// - **Fixed window**: windows are aligned to the wall clock and each one is counted
+    ///   under its own key `{prefix}{client_id}:{index}`, the index being the Unix time
+    ///   in milliseconds divided by the window. The counter is created with `add` and
+    ///   increased with `incr`, expiring once the window ends; `{prefix}{client_id}`
+    ///   records the current index for `reset`. As a new window never reuses the
+    ///   counter of the previous one, windows shorter than a second, the resolution of
+    ///   memcached expiry, are counted correctly. A request over the limit is taken back
+    ///   with `decr`: in between, concurrent requests of the client see the counter
+    ///   inflated by its cost and may be rejected although quota was left.
+    /// - **Token bucket**: `{prefix}{client_id}` holds the tokens left and when they were
+    ///   counted, updated atomically with `gets`/`cas`. A bucket left untouched for a
+    ///   window is full and expires.
+    ///
+    /// Bans are plain keys `{prefix}{client_id}:ban`, expiring with the ban. Keys that
+    /// memcached would not accept, being too long or containing whitespace, are hashed.
+    ///
+    /// Clients are spread over the servers by a hash of their key, so every instance
+    /// configured with the same server list agrees on where a client lives. Memcached
+    /// cannot list its keys, so `snapshot`, `top_keys` and `reset_prefix` are not
+    /// supported.
+    ///
+    /// # Fallback Strategy
+    ///
+    /// If memcached operations fail, the error is returned to the middleware, which
+    /// allows the request to prevent service disruption.
+    pub struct MemcachedStore {
+        /// Servers clients are spread over
+        servers: Vec<Server>,
+        /// Key prefix for namespacing rate limit data
+        prefix: String,
+        /// Algorithm used to limit clients
+        algorithm: MemcachedAlgorithm,
+        /// Timeout applied to connecting, reading and writing, `None` waiting indefinitely
+        timeout: Option<Duration>,
+    }
+
+    /// Memcached server along with its idle connections.
+    struct Server {
+        addr: String,
+        idle: Mutex<Vec<Connection>>,
+    }
+
+    /// Text protocol connection to a memcached server.
+    struct Connection {
+        stream: BufReader<TcpStream>,
+    }
+
+    /// Item read with `gets`.
+    struct Item {
+        value: String,
+        cas: u64,
+    }
+
+    impl MemcachedStore {
+        /// Creates a new [`MemcachedStore`] and tests the connection to every server.
+        ///
+        /// # Arguments
+        ///
+        /// * `servers` - Addresses of the servers, as `host:port`
+        ///
+        /// # Example
+        ///
+        /// ```rust,no_run
+        /// # #[cfg(feature = "memcached")]
+        /// # {
+        /// use actix_web_ratelimit::store::{MemcachedAlgorithm, MemcachedStore};
+        /// use std::time::Duration;
+        ///
+        /// let store = MemcachedStore::new(&["10.0.0.1:11211", "10.0.0.2:11211"])?
+        ///     .with_prefix("myapp:ratelimit:")
+        ///     .with_algorithm(MemcachedAlgorithm::TokenBucket)
+        ///     .with_timeout(Duration::from_millis(100));
+        /// # }
+        /// # Ok::<(), std::io::Error>(())
+        /// ```
+        ///
+        /// # Errors
+        ///
+        /// Returns an [`io::Error`] if no server is given or a server cannot be reached.
+        pub fn new(servers: &[&str]) -> io::Result<Self> {
+            if servers.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "no memcached server given",
+                ));
+            }
+            let store = Self {
+                servers: servers
+                    .iter()
+                    .map(|addr| Server {
+                        addr: addr.to_string(),
+                        idle: Mutex::new(Vec::new()),
+                    })
+                    .collect(),
+                prefix: MEMCACHED_PREFIX.to_string(),
+                algorithm: MemcachedAlgorithm::default(),
+                timeout: None,
+            };
+            for server in &store.servers {
+                let mut conn = store.connect(server)?;
+                conn.send(b"version\r\n")?;
+                conn.read_line()?;
+                server.release(conn);
+            }
+            Ok(store)
+        }
+
+        /// Sets a custom prefix for memcached keys.
+        pub fn with_prefix(mut self, prefix: &str) -> Self {
+            self.prefix = prefix.to_string();
+            self
+        }
+
+        /// Sets the algorithm used to limit clients, a fixed window by default.
+        pub fn with_algorithm(mut self, algorithm: MemcachedAlgorithm) -> Self {
+            self.algorithm = algorithm;
+            self
+        }
+
+        /// Sets how long to wait for connecting to a server, sending a command or reading
+        /// its reply. An operation timing out fails like any other.
+        pub fn with_timeout(mut self, timeout: Duration) -> Self {
+            self.timeout = Some(timeout);
+            // Connections opened while testing the servers wait indefinitely
+            for server in &mut self.servers {
+                server
+                    .idle
+                    .get_mut()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .clear();
+            }
+            self
+        }
+
+        /// Generates the memcached key for a client, hashing it if memcached would not
+        /// accept it.
+        ///
+        /// The prefix is kept in front of the hash as long as the result is valid, so that
+        /// hashed keys stay within the namespace.
+        fn get_key(&self, key: &str, suffix: &str) -> String {
+            let full = format!("{}{}{}", self.prefix, key, suffix);
+            if valid_key(&full) {
+                return full;
+            }
+            let hash = fnv1a(full.as_bytes());
+            let hashed = format!("{}{:016x}{}", self.prefix, hash, suffix);
+            if valid_key(&hashed) {
+                return hashed;
+            }
+            format!("{:016x}{}", hash, suffix)
+        }
+
+        /// Server holding a memcached key.
+        fn server(&self, key: &str) -> &Server {
+            &self.servers[(fnv1a(key.as_bytes()) % self.servers.len() as u64) as usize]
+        }
+
+        /// Opens a new connection to a server.
+        fn connect(&self, server: &Server) -> io::Result<Connection> {
+            let stream = match self.timeout {
+                Some(timeout) => {
+                    let addr = server.addr.to_socket_addrs()?.next().ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidInput, "unresolved address")
+                    })?;
+                    TcpStream::connect_timeout(&addr, timeout)?
+                }
+                None => TcpStream::connect(&server.addr)?,
+            };
+            stream.set_read_timeout(self.timeout)?;
+            stream.set_write_timeout(self.timeout)?;
+            stream.set_nodelay(true)?;
+            Ok(Connection {
+                stream: BufReader::new(stream),
+            })
+        }
+
+        /// Runs `op` on a connection to the server holding `key`.
+        ///
+        /// The connection is kept for later use only if `op` succeeds, as a failed one
+        /// may be left in the middle of a reply.
+        fn with_conn<T>(
+            &self,
+            key: &str,
+            op: impl FnOnce(&mut Connection) -> io::Result<T>,
+        ) -> StoreResult<T> {
+            let server = self.server(key);
+            let idle = server
+                .idle
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .pop();
+            let mut conn = match idle {
+                Some(conn) => conn,
+                None => self.connect(server)?,
+            };
+            let result = op(&mut conn)?;
+            server.release(conn);
+            Ok(result)
+        }
+
+        fn check_fixed_window(
+            &self,
+            key: &str,
+            cost: usize,
+            config: &RateLimitConfig,
+            record: bool,
+        ) -> StoreResult<RateLimitState> {
+            let window = window_ms(config);
+            let (index, left) = fixed_window(now_ms(), window);
+            let exptime = expiry(left.as_secs() + u64::from(left.subsec_nanos() > 0));
+
+            // Counters live on the server of the client, next to the current index
+            let base_key = self.get_key(key, "");
+            let mc_key = self.get_key(key, &format!(":{}", index));
+            let cost_u64 = cost as u64;
+            let (used, limited) = self.with_conn(&base_key, |conn| {
+                if !record {
+                    let used = conn.get(&mc_key)?.map_or(Ok(0), |v| parse(&v))? as usize;
+                    return Ok((used, used.saturating_add(cost) > config.max_requests));
+                }
+                let count = match conn.incr(&mc_key, cost_u64)? {
+                    Some(count) => count,
+                    None if conn.add(&mc_key, &cost.to_string(), exptime)? => {
+                        conn.set(&base_key, &index.to_string(), exptime)?;
+                        cost_u64
+                    }
+                    // Created by another request in the meantime
+                    None => conn.incr(&mc_key, cost_u64)?.unwrap_or(cost_u64),
+                };
+                let count = count as usize;
+                if count > config.max_requests {
+                    conn.decr(&mc_key, cost_u64)?;
+                    Ok((count.saturating_sub(cost), true))
+                } else {
+                    Ok((count.saturating_sub(cost), false))
+                }
+            })?;
+
+            if limited && record {
+                warn!(
+                    "Rate limit exceeded for key({}): used({}) + cost({}) > max_req({})",
+                    key, used, cost, config.max_requests
+                );
+            }
+            let used = if limited || !record {
+                used
+            } else {
+                used.saturating_add(cost)
+            };
+            Ok(RateLimitState {
+                limited,
+                used,
+                remaining: config.max_requests.saturating_sub(used),
+                retry_after: if limited {
+                    if cost <= config.max_requests {
+                        left
+                    } else {
+                        Duration::from_millis(window)
+                    }
+                } else {
+                    Duration::ZERO
+                },
+                reset_after: if used > 0 { left } else { Duration::ZERO },
+            })
+        }
+
+        fn check_token_bucket(
+            &self,
+            key: &str,
+            cost: usize,
+            config: &RateLimitConfig,
+            record: bool,
+        ) -> StoreResult<RateLimitState> {
+            let window = (config.window_secs.as_millis() as u64).max(1);
+            let capacity = (config.max_requests as u64).saturating_mul(TOKEN_SCALE);
+            let needed = (cost as u64).saturating_mul(TOKEN_SCALE);
+            let exptime = expiry(window_secs(config) + 1);
+
+            let mc_key = self.get_key(key, "");
+            let (tokens, limited) = self.with_conn(&mc_key, |conn| {
+                for _ in 0..MAX_CAS_ATTEMPTS {
+                    let now = now_ms();
+                    let item = conn.gets(&mc_key)?;
+                    let tokens = match &item {
+                        Some(item) => {
+                            let (tokens, at) = parse_bucket(&item.value)?;
+                            let refill =
+                                now.saturating_sub(at) as u128 * capacity as u128 / window as u128;
+                            (tokens as u128 + refill).min(capacity as u128) as u64
+                        }
+                        None => capacity,
+                    };
+                    if tokens < needed || !record {
+                        return Ok((tokens, tokens < needed));
+                    }
+
+                    let left = tokens - needed;
+                    let value = format!("{} {}", left, now);
+                    let stored = match item {
+                        Some(item) => conn.cas(&mc_key, &value, exptime, item.cas)?,
+                        None => conn.add(&mc_key, &value, exptime)?,
+                    };
+                    if stored {
+                        return Ok((left, false));
+                    }
+                    debug!(
+                        "Token bucket of key({}) changed concurrently, retrying",
+                        key
+                    );
+                }
+                Err(io::Error::other("too much contention on token bucket"))
+            })?;
+
+            if limited && record {
+                warn!(
+                    "Rate limit exceeded for key({}): remaining({}) < cost({})",
+                    key,
+                    tokens / TOKEN_SCALE,
+                    cost
+                );
+            }
+            // Time for the bucket to hold `target` tokens again
+            let refill_time = |target: u64| {
+                Duration::from_millis(
+                    (target.saturating_sub(tokens) as u128 * window as u128)
+                        .div_ceil(capacity.max(1) as u128) as u64,
+                )
+            };
+            let remaining = (tokens / TOKEN_SCALE) as usize;
+            Ok(RateLimitState {
+                limited,
+                used: config.max_requests - remaining,
+                remaining,
+                retry_after: match (limited, cost <= config.max_requests) {
+                    (false, _) => Duration::ZERO,
+                    (true, true) => refill_time(needed),
+                    (true, false) => config.window_secs,
+                },
+                reset_after: refill_time(capacity),
+            })
+        }
+
+        fn run_check(
+            &self,
+            key: &str,
+            cost: usize,
+            config: &RateLimitConfig,
+            record: bool,
+        ) -> StoreResult<RateLimitState> {
+            debug!(
+                "Checking rate limit for key: {} with config: max_req={}, window={:?}, cost={}",
+                key, config.max_requests, config.window_secs, cost
+            );
+            match self.algorithm {
+                MemcachedAlgorithm::FixedWindow => {
+                    self.check_fixed_window(key, cost, config, record)
+                }
+                MemcachedAlgorithm::TokenBucket => {
+                    self.check_token_bucket(key, cost, config, record)
+                }
+            }
+        }
+    }
+
+    impl Server {
+        /// Keeps a connection for later use.
+        fn release(&self, conn: Connection) {
+            let mut idle = self.idle.lock().unwrap_or_else(PoisonError::into_inner);
+            if idle.len() < MAX_IDLE_CONNECTIONS {
+                idle.push(conn);
+            }
+        }
+    }
+
+    impl Connection {
+        fn send(&mut self, command: &[u8]) -> io::Result<()> {
+            self.stream.get_mut().write_all(command)
+        }
+
+        /// Reads a reply line, failing on protocol errors.
+        fn read_line(&mut self) -> io::Result<String> {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let line = line.trim_end_matches("\r\n").to_string();
+            if line == "ERROR"
+                || line.starts_with("CLIENT_ERROR")
+                || line.starts_with("SERVER_ERROR")
+            {
+                return Err(io::Error::other(line));
+            }
+            Ok(line)
+        }
+
+        /// Sends `incr` or `decr`, returning the new value or `None` if the key is missing.
+        fn incr_decr(&mut self, op: &str, key: &str, delta: u64) -> io::Result<Option<u64>> {
+            self.send(format!("{} {} {}\r\n", op, key, delta).as_bytes())?;
+            match self.read_line()?.as_str() {
+                "NOT_FOUND" => Ok(None),
+                value => parse(value).map(Some),
+            }
+        }
+
+        fn incr(&mut self, key: &str, delta: u64) -> io::Result<Option<u64>> {
+            self.incr_decr("incr", key, delta)
+        }
+
+        fn decr(&mut self, key: &str, delta: u64) -> io::Result<Option<u64>> {
+            self.incr_decr("decr", key, delta)
+        }
+
+        /// Sends a storage command, returning whether the value was stored.
+        fn store(
+            &mut self,
+            op: &str,
+            key: &str,
+            value: &str,
+            exptime: u64,
+            cas: Option<u64>,
+        ) -> io::Result<bool> {
+            let cas = cas.map_or(String::new(), |cas| format!(" {}", cas));
+            let command = format!(
+                "{} {} 0 {} {}{}\r\n{}\r\n",
+                op,
+                key,
+                exptime,
+                value.len(),
+                cas,
+                value
+            );
+            self.send(command.as_bytes())?;
+            match self.read_line()?.as_str() {
+                "STORED" => Ok(true),
+                "NOT_STORED" | "EXISTS" | "NOT_FOUND" => Ok(false),
+                reply => Err(unexpected(reply)),
+            }
+        }
+
+        fn add(&mut self, key: &str, value: &str, exptime: u64) -> io::Result<bool> {
+            self.store("add", key, value, exptime, None)
+        }
+
+        fn set(&mut self, key: &str, value: &str, exptime: u64) -> io::Result<bool> {
+            self.store("set", key, value, exptime, None)
+        }
+
+        fn cas(&mut self, key: &str, value: &str, exptime: u64, cas: u64) -> io::Result<bool> {
+            self.store("cas", key, value, exptime, Some(cas))
+        }
+
+        /// Sends `get` or `gets`, returning the item if it exists.
+        fn retrieve(&mut self, op: &str, key: &str) -> io::Result<Option<Item>> {
+            self.send(format!("{} {}\r\n", op, key).as_bytes())?;
+            let header = self.read_line()?;
+            if header == "END" {
+                return Ok(None);
+            }
+            // VALUE <key> <flags> <bytes> [<cas unique>]
+            let fields: Vec<&str> = header.split(' ').collect();
+            if fields.len() < 4 || fields[0] != "VALUE" {
+                return Err(unexpected(&header));
+            }
+            let len = parse(fields[3])? as usize;
+            let cas = fields.get(4).map_or(Ok(0), |cas| parse(cas))?;
+
+            let mut data = vec![0; len + 2];
+            self.stream.read_exact(&mut data)?;
+            data.truncate(len);
+            let value = String::from_utf8(data)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            match self.read_line()?.as_str() {
+                "END" => Ok(Some(Item { value, cas })),
+                reply => Err(unexpected(reply)),
+            }
+        }
+
+        fn get(&mut self, key: &str) -> io::Result<Option<String>> {
+            Ok(self.retrieve("get", key)?.map(|item| item.value))
+        }
+
+        fn gets(&mut self, key: &str) -> io::Result<Option<Item>> {
+            self.retrieve("gets", key)
+        }
+
+        /// Deletes a key, returning whether it existed.
+        fn delete(&mut self, key: &str) -> io::Result<bool> {
+            self.send(format!("delete {}\r\n", key).as_bytes())?;
+            match self.read_line()?.as_str() {
+                "DELETED" => Ok(true),
+                "NOT_FOUND" => Ok(false),
+                reply => Err(unexpected(reply)),
+            }
+        }
+    }
+
+    fn unexpected(reply: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected memcached reply: {}", reply),
+        )
+    }
+
+    fn parse(value: &str) -> io::Result<u64> {
+        value.trim().parse().map_err(|_| unexpected(value))
+    }
+
+    /// Parses the `{tokens} {timestamp_ms}` value of a token bucket.
+    fn parse_bucket(value: &str) -> io::Result<(u64, u64)> {
+        let (tokens, at) = value.split_once(' ').ok_or_else(|| unexpected(value))?;
+        Ok((parse(tokens)?, parse(at)?))
+    }
+
+    /// Window of the limit in milliseconds, at least 1.
+    fn window_ms(config: &RateLimitConfig) -> u64 {
+        (config.window_secs.as_millis() as u64).max(1)
+    }
+
+    /// Index of the window aligned to the wall clock that `now_ms` falls in, along with
+    /// the time left in it.
+    fn fixed_window(now_ms: u64, window_ms: u64) -> (u64, Duration) {
+        (
+            now_ms / window_ms,
+            Duration::from_millis(window_ms - now_ms % window_ms),
+        )
+    }
+
+    /// Window of the limit in whole seconds, the resolution of memcached expiry.
+    fn window_secs(config: &RateLimitConfig) -> u64 {
+        let window = config.window_secs;
+        (window.as_secs() + u64::from(window.subsec_nanos() > 0)).max(1)
+    }
+
+    /// Whether memcached accepts `key`: short enough and free of whitespace and control
+    /// characters.
+    fn valid_key(key: &str) -> bool {
+        key.len() <= MAX_KEY_LEN && !key.bytes().any(|b| b <= b' ' || b == 0x7f)
+    }
+
+    /// Expiry to send for an item living `secs` seconds.
+    ///
+    /// Memcached reads an expiry over 30 days as a Unix timestamp, so longer ones are sent
+    /// as the time they end at.
+    fn expiry(secs: u64) -> u64 {
+        if secs > MAX_RELATIVE_EXPTIME {
+            now_ms() / 1000 + secs
+        } else {
+            secs
+        }
+    }
+
+    /// FNV-1a hash, stable across processes so that instances pick the same server.
+    fn fnv1a(bytes: &[u8]) -> u64 {
+        bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+
+    impl RateLimitStore for MemcachedStore {
+        fn check(
+            &self,
+            key: &str,
+            cost: usize,
+            config: &RateLimitConfig,
+        ) -> StoreResult<RateLimitState> {
+            self.run_check(key, cost, config, true)
+        }
+
+        fn backend(&self) -> &'static str {
+            "memcached"
+        }
+
+        fn peek(
+            &self,
+            key: &str,
+            cost: usize,
+            config: &RateLimitConfig,
+        ) -> StoreResult<RateLimitState> {
+            self.run_check(key, cost, config, false)
+        }
+
+        /// Takes `cost` back from the counter of the current window, which is only the
+        /// window of the check while its `reset_after` has not elapsed, or adds it back to
+        /// the token bucket.
+        fn refund(&self, key: &str, cost: usize, config: &RateLimitConfig) -> StoreResult<()> {
+            let mc_key = self.get_key(key, "");
+            match self.algorithm {
+                MemcachedAlgorithm::FixedWindow => {
+                    let (index, _) = fixed_window(now_ms(), window_ms(config));
+                    let window_key = self.get_key(key, &format!(":{}", index));
+                    self.with_conn(&mc_key, |conn| conn.decr(&window_key, cost as u64))?;
+                    Ok(())
+                }
+                MemcachedAlgorithm::TokenBucket => {
+                    let capacity = (config.max_requests as u64).saturating_mul(TOKEN_SCALE);
+                    let exptime = expiry(window_secs(config) + 1);
+                    self.with_conn(&mc_key, |conn| {
+                        for _ in 0..MAX_CAS_ATTEMPTS {
+                            // A missing bucket is full already
+                            let Some(item) = conn.gets(&mc_key)? else {
+                                return Ok(());
+                            };
+                            let (tokens, at) = parse_bucket(&item.value)?;
+                            let tokens = tokens
+                                .saturating_add((cost as u64).saturating_mul(TOKEN_SCALE))
+                                .min(capacity);
+                            let value = format!("{} {}", tokens, at);
+                            if conn.cas(&mc_key, &value, exptime, item.cas)? {
+                                return Ok(());
+                            }
+                        }
+                        Err(io::Error::other("too much contention on token bucket"))
+                    })
+                }
+            }
+        }
+
+        /// Deletes the token bucket, or the counter of the current window along with its
+        /// index.
+        fn reset(&self, key: &str) -> StoreResult<()> {
+            let mc_key = self.get_key(key, "");
+            self.with_conn(&mc_key, |conn| {
+                if self.algorithm == MemcachedAlgorithm::FixedWindow
+                    && let Some(index) = conn.get(&mc_key)?
+                {
+                    conn.delete(&self.get_key(key, &format!(":{}", index.trim())))?;
+                }
+                conn.delete(&mc_key)
+            })?;
+            Ok(())
+        }
+
+        fn ban(&self, key: &str, duration: Option<Duration>) -> StoreResult<()> {
+            let ban_key = self.get_key(key, BAN_SUFFIX);
+            // Expiry is in whole seconds, 0 keeping the key until deleted
+            let exptime = duration.map_or(0, |d| expiry(d.as_secs().max(1)));
+            self.with_conn(&ban_key, |conn| conn.set(&ban_key, "1", exptime))?;
+            Ok(())
+        }
+
+        fn unban(&self, key: &str) -> StoreResult<()> {
+            let ban_key = self.get_key(key, BAN_SUFFIX);
+            self.with_conn(&ban_key, |conn| conn.delete(&ban_key))?;
+            Ok(())
+        }
+
+        fn is_banned(&self, key: &str) -> StoreResult<bool> {
+            let ban_key = self.get_key(key, BAN_SUFFIX);
+            Ok(self
+                .with_conn(&ban_key, |conn| conn.get(&ban_key))?
+                .is_some())
+        }
+    }
+
+    impl From<io::Error> for StoreError {
+        fn from(err: io::Error) -> Self {
+            StoreError::Memcached(err)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn store() -> MemcachedStore {
+            MemcachedStore {
+                servers: Vec::new(),
+                prefix: MEMCACHED_PREFIX.to_string(),
+                algorithm: MemcachedAlgorithm::FixedWindow,
+                timeout: None,
+            }
+        }
+
+        #[test]
+        fn windows_are_indexed_by_wall_clock() {
+            assert_eq!(fixed_window(1_500, 1_000), (1, Duration::from_millis(500)));
+            assert_eq!(
+                fixed_window(2_000, 1_000),
+                (2, Duration::from_millis(1_000))
+            );
+            // Sub-second windows get an index of their own
+            assert_eq!(fixed_window(1_100, 250), (4, Duration::from_millis(150)));
+            assert_eq!(fixed_window(1_250, 250), (5, Duration::from_millis(250)));
+        }
+
+        #[test]
+        fn window_keys_keep_the_index_when_hashed() {
+            let store = store();
+            assert_eq!(store.get_key("client", ":4"), "rate_limit:client:4");
+
+            let hashed = store.get_key(&"client with spaces ".repeat(20), ":4");
+            assert!(valid_key(&hashed));
+            assert!(hashed.starts_with(MEMCACHED_PREFIX));
+            assert!(hashed.ends_with(":4"));
+        }
+
+        #[test]
+        fn long_expiries_are_sent_as_unix_times() {
+            assert_eq!(expiry(10), 10);
+            assert_eq!(expiry(MAX_RELATIVE_EXPTIME), MAX_RELATIVE_EXPTIME);
+            assert!(expiry(MAX_RELATIVE_EXPTIME + 1) > now_ms() / 1000);
+        }
+    }
+}
+
+#[cfg(feature = "memcached")]
+pub use memcached_store_impl::{MemcachedAlgorithm, MemcachedStore};
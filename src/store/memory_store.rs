@@ -1,7 +1,21 @@
 use dashmap::DashMap;
-use std::{sync::Arc, time::Instant};
+use log::warn;
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
 
-use crate::{config::RateLimitConfig, store::RateLimitStore};
+use crate::{
+    config::{ConcurrencyConfig, RateLimitConfig},
+    store::{ConcurrencyStore, RateLimitState, RateLimitStore, StoreResult, now_ms},
+};
+
+/// Number of locks serializing the checks of clients
+const LOCK_STRIPES: usize = 64;
 
 /// In-memory implementation of [`RateLimitStore`] using DashMap for concurrent access.
 ///
@@ -17,12 +31,20 @@ use crate::{config::RateLimitConfig, store::RateLimitStore};
 ///
 /// # Limitations
 ///
-/// - Data is lost on application restart
+/// - Data is lost on application restart, unless written with [`MemoryStore::save`]
+///   on shutdown and read back with [`MemoryStore::restore`] on startup
 /// - Not suitable for distributed systems
 /// - Memory usage can grow if clients are not cleaned up
 pub struct MemoryStore {
-    /// Thread-safe map storing client identifiers and their request timestamps
-    pub store: DashMap<String, Vec<Instant>>,
+    /// Thread-safe map storing client identifiers and their request timestamps with costs
+    pub store: DashMap<String, Vec<(Instant, usize)>>,
+    /// Thread-safe map storing client identifiers and their number of in-flight requests
+    pub in_flight: DashMap<String, usize>,
+    /// Thread-safe map storing banned client identifiers and when their ban expires
+    pub bans: DashMap<String, Option<Instant>>,
+    /// Locks taken by checks, each covering the clients whose key hashes to it, so that
+    /// [`RateLimitStore::check_all`] can hold several clients at once
+    locks: Box<[Mutex<()>]>,
 }
 
 impl MemoryStore {
@@ -43,6 +65,9 @@ impl MemoryStore {
     pub fn new() -> Self {
         Self {
             store: DashMap::new(),
+            in_flight: DashMap::new(),
+            bans: DashMap::new(),
+            locks: (0..LOCK_STRIPES).map(|_| Mutex::new(())).collect(),
         }
     }
 }
@@ -56,46 +81,676 @@ impl Default for MemoryStore {
     }
 }
 
+impl MemoryStore {
+    /// Index of the lock covering a client.
+    fn lock_index(&self, key: &str) -> usize {
+        self.store.hash_usize(&key) % self.locks.len()
+    }
+
+    /// Drops the expired entries of a client and computes its state.
+    fn current(
+        &self,
+        key: &str,
+        now: Instant,
+        cost: usize,
+        config: &RateLimitConfig,
+    ) -> RateLimitState {
+        match self.store.get_mut(key) {
+            Some(mut entry) => {
+                let entries = entry.value_mut();
+                entries.retain(|&(t, _)| now.duration_since(t) <= config.window_secs);
+                Self::evaluate(entries, now, cost, config)
+            }
+            None => Self::evaluate(&[], now, cost, config),
+        }
+    }
+
+    /// Records a request the client's state allowed, updating the state accordingly.
+    fn record(
+        &self,
+        key: &str,
+        now: Instant,
+        cost: usize,
+        config: &RateLimitConfig,
+        state: &mut RateLimitState,
+    ) {
+        self.store
+            .entry(key.to_string())
+            .or_default()
+            .push((now, cost));
+        state.used = state.used.saturating_add(cost);
+        state.remaining -= cost;
+        state.reset_after = config.window_secs;
+    }
+
+    /// Computes the state of a client from its unexpired entries without recording anything.
+    fn evaluate(
+        entries: &[(Instant, usize)],
+        now: Instant,
+        cost: usize,
+        config: &RateLimitConfig,
+    ) -> RateLimitState {
+        let window = config.window_secs;
+        let used = entries
+            .iter()
+            .fold(0usize, |used, &(_, c)| used.saturating_add(c));
+        let expires_in = |t: Instant| window.saturating_sub(now.duration_since(t));
+        let limited = used.saturating_add(cost) > config.max_requests;
+
+        let mut retry_after = Duration::ZERO;
+        if limited {
+            // Walk from the oldest entry until enough units would be freed
+            retry_after = window;
+            if cost <= config.max_requests {
+                let mut freed = 0;
+                for &(t, c) in entries {
+                    freed = c.saturating_add(freed);
+                    if used.saturating_sub(freed).saturating_add(cost) <= config.max_requests {
+                        retry_after = expires_in(t);
+                        break;
+                    }
+                }
+            }
+        }
+
+        RateLimitState {
+            limited,
+            used,
+            remaining: config.max_requests.saturating_sub(used),
+            retry_after,
+            reset_after: entries
+                .last()
+                .map_or(Duration::ZERO, |&(t, _)| expires_in(t)),
+        }
+    }
+}
+
 impl RateLimitStore for MemoryStore {
     /// Checks if the client has exceeded the rate limit and records the current request.
     ///
     /// This method implements the sliding window algorithm:
     /// 1. Gets or creates an entry for the client key
     /// 2. Removes expired timestamps outside the time window
-    /// 3. Checks if the remaining request count exceeds the limit
-    /// 4. If not exceeded, records the current timestamp
+    /// 3. Checks if the recorded cost plus the request cost exceeds the limit
+    /// 4. If not exceeded, records the current timestamp with its cost
+    ///
+    /// The entry stays locked for the whole operation, so the cost is deducted atomically.
     ///
     /// # Arguments
     ///
     /// * `key` - Client identifier (typically IP address)
+    /// * `cost` - Number of units this request consumes
     /// * `config` - Rate limiting configuration
     ///
-    /// # Returns
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    /// use actix_web_ratelimit::store::{MemoryStore, RateLimitStore};
+    ///
+    /// let store = MemoryStore::new();
+    /// let config = RateLimitConfig::default().max_requests(10);
+    ///
+    /// let state = store.check("client", 7, &config).unwrap();
+    /// assert!(!state.limited);
+    /// assert_eq!(state.remaining, 3);
+    ///
+    /// // Not enough quota left for another expensive request
+    /// assert!(store.check("client", 7, &config).unwrap().limited);
+    /// ```
+    fn check(
+        &self,
+        key: &str,
+        cost: usize,
+        config: &RateLimitConfig,
+    ) -> StoreResult<RateLimitState> {
+        let _lock = self.locks[self.lock_index(key)]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        let mut state = self.current(key, now, cost, config);
+        if state.limited {
+            warn!(
+                "Rate limit exceeded for key({}): used({}) + cost({}) > max_req({})",
+                key, state.used, cost, config.max_requests
+            );
+        } else {
+            self.record(key, now, cost, config, &mut state);
+        }
+        Ok(state)
+    }
+
+    /// Checks every limit before recording the request in any of them.
+    ///
+    /// The locks of all clients are taken in a fixed order and held until the request is
+    /// recorded, so concurrent requests never see units of a request that was rejected.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    /// use actix_web_ratelimit::store::{MemoryStore, RateLimitStore};
+    ///
+    /// let store = MemoryStore::new();
+    /// let client = RateLimitConfig::default().max_requests(5);
+    /// let global = RateLimitConfig::default().max_requests(1);
+    ///
+    /// assert!(!store.check_all(&[("client", &client), ("global", &global)], 1).unwrap().limited);
+    /// assert!(store.check_all(&[("client", &client), ("global", &global)], 1).unwrap().limited);
+    ///
+    /// // The rejected request was not recorded for the client
+    /// assert_eq!(store.peek("client", 0, &client).unwrap().used, 1);
+    /// ```
+    fn check_all(
+        &self,
+        checks: &[(&str, &RateLimitConfig)],
+        cost: usize,
+    ) -> StoreResult<RateLimitState> {
+        let mut indices: Vec<usize> = checks
+            .iter()
+            .map(|&(key, _)| self.lock_index(key))
+            .collect();
+        indices.sort_unstable();
+        indices.dedup();
+        let _locks: Vec<_> = indices
+            .iter()
+            .map(|&i| self.locks[i].lock().unwrap_or_else(PoisonError::into_inner))
+            .collect();
+
+        let now = Instant::now();
+        let mut states = Vec::with_capacity(checks.len());
+        for &(key, config) in checks {
+            let state = self.current(key, now, cost, config);
+            if state.limited {
+                warn!(
+                    "Rate limit exceeded for key({}): used({}) + cost({}) > max_req({})",
+                    key, state.used, cost, config.max_requests
+                );
+                return Ok(state);
+            }
+            states.push(state);
+        }
+
+        let mut result = RateLimitState::UNLIMITED;
+        for (&(key, config), mut state) in checks.iter().zip(states) {
+            self.record(key, now, cost, config, &mut state);
+            result = result.most_restrictive(state);
+        }
+        Ok(result)
+    }
+
+    fn backend(&self) -> &'static str {
+        "memory"
+    }
+
+    /// Returns the state of the client as if a request of `cost` units were checked,
+    /// without recording it.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    /// use actix_web_ratelimit::store::{MemoryStore, RateLimitStore};
+    ///
+    /// let store = MemoryStore::new();
+    /// let config = RateLimitConfig::default().max_requests(1);
+    ///
+    /// assert!(!store.peek("client", 1, &config).unwrap().limited);
+    /// assert!(!store.peek("client", 1, &config).unwrap().limited);
+    /// ```
+    fn peek(
+        &self,
+        key: &str,
+        cost: usize,
+        config: &RateLimitConfig,
+    ) -> StoreResult<RateLimitState> {
+        Ok(self.current(key, Instant::now(), cost, config))
+    }
+
+    /// Removes the most recent request of `cost` units still within the window.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    /// use actix_web_ratelimit::store::{MemoryStore, RateLimitStore};
+    ///
+    /// let store = MemoryStore::new();
+    /// let config = RateLimitConfig::default().max_requests(1);
+    ///
+    /// store.check("client", 1, &config).unwrap();
+    /// store.refund("client", 1, &config).unwrap();
+    /// assert!(!store.check("client", 1, &config).unwrap().limited);
+    /// ```
+    fn refund(&self, key: &str, cost: usize, _config: &RateLimitConfig) -> StoreResult<()> {
+        if let Some(mut entry) = self.store.get_mut(key) {
+            let entries = entry.value_mut();
+            if let Some(pos) = entries.iter().rposition(|&(_, c)| c == cost) {
+                entries.remove(pos);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes all requests recorded for the client.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    /// use actix_web_ratelimit::store::{MemoryStore, RateLimitStore};
+    ///
+    /// let store = MemoryStore::new();
+    /// let config = RateLimitConfig::default().max_requests(1);
+    ///
+    /// store.check("client", 1, &config).unwrap();
+    /// store.reset("client").unwrap();
+    /// assert_eq!(store.peek("client", 0, &config).unwrap().used, 0);
+    /// ```
+    fn reset(&self, key: &str) -> StoreResult<()> {
+        self.store.remove(key);
+        Ok(())
+    }
+
+    /// Bans the client until `duration` elapses, or until unbanned.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::store::{MemoryStore, RateLimitStore};
+    ///
+    /// let store = MemoryStore::new();
+    ///
+    /// store.ban("client", None).unwrap();
+    /// assert!(store.is_banned("client").unwrap());
+    ///
+    /// store.unban("client").unwrap();
+    /// assert!(!store.is_banned("client").unwrap());
+    /// ```
+    fn ban(&self, key: &str, duration: Option<Duration>) -> StoreResult<()> {
+        // A ban outlasting what an `Instant` can represent never expires
+        let expires = duration.and_then(|d| Instant::now().checked_add(d));
+        self.bans.insert(key.to_string(), expires);
+        Ok(())
+    }
+
+    fn unban(&self, key: &str) -> StoreResult<()> {
+        self.bans.remove(key);
+        Ok(())
+    }
+
+    /// Returns whether the client is banned, dropping its ban once it has expired.
+    fn is_banned(&self, key: &str) -> StoreResult<bool> {
+        let now = Instant::now();
+        self.bans
+            .remove_if(key, |_, expires| expires.is_some_and(|t| t <= now));
+        Ok(self.bans.contains_key(key))
+    }
+
+    /// Removes the entries of all clients whose identifier starts with `prefix`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    /// use actix_web_ratelimit::store::{MemoryStore, RateLimitStore};
+    ///
+    /// let store = MemoryStore::new();
+    /// let config = RateLimitConfig::default();
+    ///
+    /// store.check("user:1", 1, &config).unwrap();
+    /// store.check("user:2", 1, &config).unwrap();
+    /// store.check("ip:1.2.3.4", 1, &config).unwrap();
+    ///
+    /// assert_eq!(store.reset_prefix("user:").unwrap(), 2);
+    /// assert_eq!(store.reset_all().unwrap(), 1);
+    /// ```
+    fn reset_prefix(&self, prefix: &str) -> StoreResult<usize> {
+        let before = self.store.len();
+        self.store.retain(|key, _| !key.starts_with(prefix));
+        Ok(before.saturating_sub(self.store.len()))
+    }
+
+    /// Sums the unexpired costs of every client in the map.
+    ///
+    /// Walks every entry of the map, so it is meant for occasional inspection rather
+    /// than the request path.
     ///
-    /// `true` if the client has exceeded the rate limit, `false` otherwise
-    fn is_limited(&self, key: &str, config: &RateLimitConfig) -> bool {
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    /// use actix_web_ratelimit::store::{MemoryStore, RateLimitStore};
+    ///
+    /// let store = MemoryStore::new();
+    /// let config = RateLimitConfig::default();
+    ///
+    /// store.check("light", 1, &config).unwrap();
+    /// store.check("heavy", 5, &config).unwrap();
+    ///
+    /// assert_eq!(store.snapshot(&config).unwrap().len(), 2);
+    ///
+    /// let top = store.top_keys(1, &config).unwrap();
+    /// assert_eq!(top, vec![("heavy".to_string(), 5)]);
+    /// ```
+    fn snapshot(&self, config: &RateLimitConfig) -> StoreResult<Vec<(String, usize)>> {
         let now = Instant::now();
-        let mut entry = self.store.entry(key.to_string()).or_default();
-        let timestamps = entry.value_mut();
+        Ok(self
+            .store
+            .iter()
+            .map(|entry| {
+                let used = entry
+                    .value()
+                    .iter()
+                    .filter(|&&(t, _)| now.duration_since(t) <= config.window_secs)
+                    .fold(0usize, |used, &(_, c)| used.saturating_add(c));
+                (entry.key().clone(), used)
+            })
+            .filter(|&(_, used)| used > 0)
+            .collect())
+    }
+}
+
+/// First line of the files written by [`MemoryStore::save`]
+const SNAPSHOT_HEADER: &str = "actix-web-ratelimit memory-store 1";
+
+impl MemoryStore {
+    /// Writes the recorded requests and active bans to `path`, so that a restarted
+    /// application can read them back with [`MemoryStore::restore`] and clients cannot
+    /// reset their limits by waiting for a deploy.
+    ///
+    /// Timestamps are converted to wall-clock time, as an [`Instant`] is meaningless to
+    /// another process. In-flight requests are not saved. The file is written next to
+    /// `path` first and then renamed, so a crash never leaves a truncated snapshot.
+    ///
+    /// # Returns
+    ///
+    /// The number of clients saved, or an [`io::Error`] if the file cannot be written.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::RateLimitConfig;
+    /// use actix_web_ratelimit::store::{MemoryStore, RateLimitStore};
+    /// use std::time::Duration;
+    ///
+    /// let path = std::env::temp_dir().join("ratelimit-save-example.state");
+    /// let config = RateLimitConfig::default().max_requests(1);
+    ///
+    /// let store = MemoryStore::new();
+    /// store.check("client", 1, &config).unwrap();
+    /// store.ban("abuser", Some(Duration::from_secs(600))).unwrap();
+    /// store.save(&path)?;
+    ///
+    /// // After a restart
+    /// let store = MemoryStore::new();
+    /// store.restore(&path, Duration::from_secs(60))?;
+    /// assert!(store.check("client", 1, &config).unwrap().limited);
+    /// assert!(store.is_banned("abuser").unwrap());
+    /// # std::fs::remove_file(&path)?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<usize> {
+        let path = path.as_ref();
+        let mut tmp = OsString::from(path);
+        tmp.push(".tmp");
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        writeln!(out, "{}", SNAPSHOT_HEADER)?;
 
-        // Keep only timestamps within the time window
-        timestamps.retain(|&t| now.duration_since(t) <= config.window_secs);
-        if timestamps.len() > config.max_requests {
-            return true;
+        let (now, wall) = (Instant::now(), now_ms());
+        let mut saved = 0;
+        for entry in self.store.iter() {
+            if entry.value().is_empty() {
+                continue;
+            }
+            write!(out, "r {}", escape(entry.key()))?;
+            for &(t, cost) in entry.value() {
+                let at = wall.saturating_sub(now.saturating_duration_since(t).as_millis() as u64);
+                write!(out, " {}:{}", at, cost)?;
+            }
+            writeln!(out)?;
+            saved += 1;
+        }
+        for ban in self.bans.iter() {
+            match *ban.value() {
+                Some(expires) if expires <= now => {}
+                Some(expires) => {
+                    let left = expires.duration_since(now).as_millis();
+                    let at = wall.saturating_add(u64::try_from(left).unwrap_or(u64::MAX));
+                    writeln!(out, "b {} {}", escape(ban.key()), at)?;
+                }
+                None => writeln!(out, "b {} -", escape(ban.key()))?,
+            }
         }
 
-        timestamps.push(now);
-        false
+        out.into_inner()?.sync_all()?;
+        fs::rename(&tmp, path)?;
+        Ok(saved)
+    }
+
+    /// Reads back the requests and bans written by [`MemoryStore::save`], adding them to
+    /// those already recorded.
+    ///
+    /// Requests older than `max_age`, typically the longest window in use, and expired
+    /// bans are skipped. A missing file is not an error, so the same call works on the
+    /// first start.
+    ///
+    /// # Returns
+    ///
+    /// The number of clients restored, or an [`io::Error`] if the file cannot be read
+    /// or was not written by [`MemoryStore::save`].
+    pub fn restore(&self, path: impl AsRef<Path>, max_age: Duration) -> io::Result<usize> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err),
+        };
+        let mut lines = BufReader::new(file).lines();
+        if lines.next().transpose()?.as_deref() != Some(SNAPSHOT_HEADER) {
+            return Err(invalid("not a rate limit snapshot"));
+        }
+
+        let (now, wall) = (Instant::now(), now_ms());
+        let mut restored = 0;
+        for line in lines {
+            let line = line?;
+            let mut fields = line.split(' ');
+            let (kind, key) = match (fields.next(), fields.next()) {
+                (Some(kind), Some(key)) => (kind, unescape(key)?),
+                _ => return Err(invalid("truncated line")),
+            };
+            match kind {
+                "r" => {
+                    let mut entries = Vec::new();
+                    for field in fields {
+                        let (at, cost) = field.split_once(':').ok_or_else(|| invalid(field))?;
+                        let age = Duration::from_millis(wall.saturating_sub(parse(at)?));
+                        // Older than this process can represent, long expired anyway
+                        if let Some(t) = now.checked_sub(age)
+                            && age <= max_age
+                        {
+                            let cost = usize::try_from(parse(cost)?).map_err(|_| invalid(field))?;
+                            entries.push((t, cost));
+                        }
+                    }
+                    if !entries.is_empty() {
+                        let mut recorded = self.store.entry(key).or_default();
+                        recorded.extend(entries);
+                        recorded.sort_by_key(|&(t, _)| t);
+                        restored += 1;
+                    }
+                }
+                "b" => match fields.next() {
+                    Some("-") => {
+                        self.bans.insert(key, None);
+                    }
+                    Some(at) => {
+                        let at = parse(at)?;
+                        if at > wall {
+                            let expires = now.checked_add(Duration::from_millis(at - wall));
+                            self.bans.insert(key, expires);
+                        }
+                    }
+                    None => return Err(invalid("truncated line")),
+                },
+                _ => return Err(invalid(kind)),
+            }
+        }
+        Ok(restored)
     }
 }
 
-/// Implementation of [`RateLimitStore`] for `Arc<MemoryStore>` to enable shared ownership.
-///
-/// This allows the same `MemoryStore` instance to be used across multiple threads
-/// and middleware instances safely.
-impl RateLimitStore for Arc<MemoryStore> {
-    /// Delegates to the underlying `MemoryStore` implementation.
-    fn is_limited(&self, key: &str, config: &RateLimitConfig) -> bool {
-        (**self).is_limited(key, config)
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid rate limit snapshot: {}", what),
+    )
+}
+
+fn parse(value: &str) -> io::Result<u64> {
+    value.parse().map_err(|_| invalid(value))
+}
+
+/// Percent-encodes the whitespace, control characters and `%` of a client identifier,
+/// keeping it a single field of its line.
+fn escape(key: &str) -> String {
+    let mut escaped = String::with_capacity(key.len());
+    for c in key.chars() {
+        if c == '%' || c.is_whitespace() || c.is_control() {
+            let mut buf = [0; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                escaped.push_str(&format!("%{:02X}", b));
+            }
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// Reverses [`escape`].
+fn unescape(field: &str) -> io::Result<String> {
+    let mut bytes = Vec::with_capacity(field.len());
+    let mut rest = field.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = tail
+                .get(..2)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| invalid(field))?;
+            bytes.push(hex);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| invalid(field))
+}
+
+impl ConcurrencyStore for MemoryStore {
+    /// Increments the client's in-flight counter if it is below `max_concurrent`.
+    ///
+    /// Permits are released by the middleware when the request finishes, so no lease
+    /// expiry is needed within a single process.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use actix_web_ratelimit::config::ConcurrencyConfig;
+    /// use actix_web_ratelimit::store::{ConcurrencyStore, MemoryStore};
+    ///
+    /// let store = MemoryStore::new();
+    /// let config = ConcurrencyConfig::default().max_concurrent(1);
+    ///
+    /// let lease = store.acquire("client", &config).unwrap().unwrap();
+    /// assert!(store.acquire("client", &config).unwrap().is_none());
+    ///
+    /// store.release("client", lease).unwrap();
+    /// assert!(store.acquire("client", &config).unwrap().is_some());
+    /// ```
+    fn acquire(&self, key: &str, config: &ConcurrencyConfig) -> StoreResult<Option<u64>> {
+        let mut count = self.in_flight.entry(key.to_string()).or_default();
+        if *count >= config.max_concurrent {
+            return Ok(None);
+        }
+        *count += 1;
+        Ok(Some(0))
+    }
+
+    /// Decrements the client's in-flight counter, removing it once it reaches zero.
+    fn release(&self, key: &str, _lease: u64) -> StoreResult<()> {
+        self.in_flight.remove_if_mut(key, |_, count| {
+            *count = count.saturating_sub(1);
+            *count == 0
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(name: &str, body: &str) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("ratelimit-{}-{}.state", name, std::process::id()));
+        fs::write(&path, format!("{}\n{}", SNAPSHOT_HEADER, body)).unwrap();
+        path
+    }
+
+    #[test]
+    fn restore_saturates_huge_costs() {
+        let at = now_ms();
+        let path = snapshot(
+            "huge",
+            &format!("r client {at}:{max} {at}:{max}\n", max = usize::MAX),
+        );
+        let store = MemoryStore::new();
+        assert_eq!(store.restore(&path, Duration::from_secs(60)).unwrap(), 1);
+        fs::remove_file(&path).unwrap();
+
+        let config = RateLimitConfig::default().max_requests(10);
+        let state = store.check("client", 1, &config).unwrap();
+        assert!(state.limited);
+        assert_eq!(state.used, usize::MAX);
+    }
+
+    #[test]
+    fn restore_rejects_malformed_files() {
+        let at = now_ms();
+        for body in [
+            format!("r client {at}:18446744073709551616\n"),
+            format!("r client {at}:-1\n"),
+            "r client 12\n".to_string(),
+            "b\n".to_string(),
+            "x client\n".to_string(),
+        ] {
+            let path = snapshot("malformed", &body);
+            let err = MemoryStore::new()
+                .restore(&path, Duration::from_secs(60))
+                .unwrap_err();
+            fs::remove_file(&path).unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", body);
+        }
+    }
+
+    #[test]
+    fn long_bans_round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("ratelimit-bans-{}.state", std::process::id()));
+        let store = MemoryStore::new();
+        store.ban("forever", Some(Duration::MAX)).unwrap();
+        store
+            .ban("decade", Some(Duration::from_secs(10 * 365 * 24 * 60 * 60)))
+            .unwrap();
+        store.save(&path).unwrap();
+
+        let restored = MemoryStore::new();
+        restored.restore(&path, Duration::from_secs(60)).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(restored.is_banned("forever").unwrap());
+        assert!(restored.is_banned("decade").unwrap());
     }
 }
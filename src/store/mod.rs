@@ -1,9 +1,37 @@
+mod batching_store;
+mod circuit_breaker;
+mod error;
+mod hybrid_store;
+#[cfg(feature = "memcached")]
+mod memcached_store;
 mod memory_store;
 #[cfg(feature = "redis")]
 mod redis_store;
+mod sharded_memory_store;
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+mod sql_store;
 mod traits;
 
+pub use batching_store::BatchingStore;
+pub use circuit_breaker::{CircuitBreaker, CircuitState};
+pub use error::{StoreError, StoreFuture, StoreResult};
+pub use hybrid_store::HybridStore;
+#[cfg(feature = "memcached")]
+pub use memcached_store::{MemcachedAlgorithm, MemcachedStore};
 pub use memory_store::MemoryStore;
 #[cfg(feature = "redis")]
-pub use redis_store::RedisStore;
-pub use traits::RateLimitStore;
+pub use redis_store::{RedisStore, RedisStoreBuilder};
+pub use sharded_memory_store::ShardedMemoryStore;
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+pub use sql_store::SqlStore;
+pub use traits::{ConcurrencyStore, RateLimitState, RateLimitStore};
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Milliseconds since the Unix epoch, used by stores whose state outlives the process.
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
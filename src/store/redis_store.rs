@@ -1,14 +1,129 @@
 #[cfg(feature = "redis")]
 mod redis_store_impl {
-    use crate::{config::RateLimitConfig, store::RateLimitStore};
-    use log::{debug, error, warn};
-    use redis::{Client, RedisError, RedisResult};
-    use std::sync::Arc;
+    use crate::{
+        config::{ConcurrencyConfig, RateLimitConfig},
+        store::{
+            ConcurrencyStore, RateLimitState, RateLimitStore, StoreError, StoreResult,
+            traits::check_each,
+        },
+    };
+    use log::{debug, warn};
+    use redis::{Client, ConnectionLike, ErrorKind, RedisError, RedisResult, Script, Value};
+    use std::{
+        collections::hash_map::RandomState,
+        hash::BuildHasher,
+        sync::atomic::{AtomicU64, Ordering},
+        time::Duration,
+    };
 
     /// Default prefix for Redis keys used by the rate limiter
     const REDIS_PREFIX: &str = "rate_limit:";
 
-    /// Redis-based implementation of [`RateLimitStore`] using Redis Sorted Sets.
+    /// Suffix of the keys holding in-flight leases
+    const INFLIGHT_SUFFIX: &str = ":inflight";
+
+    /// Suffix of the keys marking a banned client
+    const BAN_SUFFIX: &str = ":ban";
+
+    /// Atomic sliding window check over one or more keys.
+    ///
+    /// The request is recorded in every key only if it fits within all of their limits.
+    ///
+    /// KEYS: sorted sets of `{timestamp}:{nonce}:{cost}` members scored by timestamp
+    /// ARGV: now (ms), cost, member, record (0 or 1),
+    ///       then window (ms) and max units for each key
+    ///
+    /// Returns `{limited, used, remaining, retry_after_ms, reset_after_ms}` for each key.
+    const CHECK_SCRIPT: &str = r#"
+local now = tonumber(ARGV[1])
+local cost = tonumber(ARGV[2])
+local states = {}
+local any_limited = false
+
+for k = 1, #KEYS do
+    local window = tonumber(ARGV[3 + k * 2])
+    local max = tonumber(ARGV[4 + k * 2])
+
+    redis.call('ZREMRANGEBYSCORE', KEYS[k], '-inf', now - window)
+    local entries = redis.call('ZRANGE', KEYS[k], 0, -1, 'WITHSCORES')
+    local used = 0
+    for i = 1, #entries, 2 do
+        used = used + tonumber(string.match(entries[i], ':(%d+)$'))
+    end
+    local reset = 0
+    if #entries > 0 then
+        reset = tonumber(entries[#entries]) + window - now
+    end
+
+    if used + cost > max then
+        any_limited = true
+        local retry = window
+        if cost <= max then
+            local freed = 0
+            for i = 1, #entries, 2 do
+                freed = freed + tonumber(string.match(entries[i], ':(%d+)$'))
+                if used - freed + cost <= max then
+                    retry = tonumber(entries[i + 1]) + window - now
+                    break
+                end
+            end
+        end
+        states[k] = {1, used, math.max(max - used, 0), retry, reset}
+    else
+        states[k] = {0, used, max - used, 0, reset}
+    end
+end
+
+if ARGV[4] == '1' and not any_limited then
+    for k = 1, #KEYS do
+        redis.call('ZADD', KEYS[k], now, ARGV[3])
+        redis.call('PEXPIRE', KEYS[k], ARGV[3 + k * 2])
+        local state = states[k]
+        states[k] = {0, state[2] + cost, state[3] - cost, 0, tonumber(ARGV[3 + k * 2])}
+    end
+end
+
+local result = {}
+for k = 1, #KEYS do
+    for j = 1, 5 do
+        table.insert(result, states[k][j])
+    end
+end
+return result
+"#;
+
+    /// Removes the most recent member of the given cost.
+    ///
+    /// KEYS[1]: sorted set used by [`CHECK_SCRIPT`]
+    /// ARGV: cost
+    const REFUND_SCRIPT: &str = r#"
+local members = redis.call('ZREVRANGE', KEYS[1], 0, -1)
+for i = 1, #members do
+    if string.match(members[i], ':(%d+)$') == ARGV[1] then
+        return redis.call('ZREM', KEYS[1], members[i])
+    end
+end
+return 0
+"#;
+
+    /// Takes an in-flight permit if the client holds fewer than the maximum.
+    ///
+    /// KEYS[1]: sorted set of lease members scored by their expiry time
+    /// ARGV: now (ms), max permits, member, lease (ms)
+    const ACQUIRE_SCRIPT: &str = r#"
+local now = tonumber(ARGV[1])
+local lease = tonumber(ARGV[4])
+
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now)
+if redis.call('ZCARD', KEYS[1]) >= tonumber(ARGV[2]) then
+    return 0
+end
+redis.call('ZADD', KEYS[1], now + lease, ARGV[3])
+redis.call('PEXPIRE', KEYS[1], lease)
+return 1
+"#;
+
+    /// Redis-based implementation of [`RateLimitStore`] and [`ConcurrencyStore`] using Redis Sorted Sets.
     ///
     /// This store uses Redis Sorted Sets to track request timestamps for each client.
     /// It's suitable for distributed applications where rate limiting data needs
@@ -19,24 +134,553 @@ mod redis_store_impl {
     /// - **Distributed**: Share rate limiting data across multiple application instances
     /// - **Persistent**: Data survives application restarts
     /// - **Scalable**: Can handle high throughput with proper Redis configuration
-    /// - **Automatic cleanup**: Uses Redis expiration to clean up old data
+    /// - **Automatic cleanup**: Keys expire with `PEXPIRE` once their window has passed
     ///
     /// # Redis Data Structure
     ///
     /// Uses Redis Sorted Sets where:
     /// - Key: `{prefix}{client_id}`
     /// - Score: Request timestamp in milliseconds
-    /// - Member: Same as score (timestamp)
+    /// - Member: `{timestamp}:{nonce}:{cost}`, unique per request and carrying its cost
+    ///
+    /// The whole check runs as a single Lua script, so the cost is deducted atomically
+    /// even when several application instances share the same key.
+    ///
+    /// In-flight permits are kept in a separate sorted set `{prefix}{client_id}:inflight`
+    /// whose members are leases scored by their expiry time, so permits held by a crashed
+    /// instance are reclaimed after `lease_secs`.
+    ///
+    /// Bans are plain keys `{prefix}{client_id}:ban`, expiring with the ban.
+    ///
+    /// # Redis Cluster
+    ///
+    /// [`RedisStore::new_cluster`] connects to a Redis Cluster (requires `redis-cluster`
+    /// feature). Keys then carry a `{hash tag}` so that a client's sorted set, leases and
+    /// ban live in the same slot: `{prefix}{{client_id}}`. Limits of different clients,
+    /// such as a per-client and a [`crate::config::GlobalLimit`], are checked one after
+    /// the other unless [`RedisStore::with_shared_slot`] places every key in one slot.
+    /// Scanning operations, i.e. `snapshot`, `top_keys` and `reset_prefix`, are not
+    /// supported on a cluster. Cluster connections are kept between operations and only
+    /// replaced after failing, so the topology is not discovered again on every request.
+    ///
+    /// # Redis Sentinel
+    ///
+    /// [`RedisStore::new_sentinel`] locates the primary through Sentinel (requires
+    /// `redis-sentinel` feature). Every operation runs on the primary, never on a replica.
+    /// When the primary becomes unreachable or answers `READONLY` after a failover, the
+    /// operation fails and the next one asks the sentinels for the new primary.
     ///
     /// # Fallback Strategy
     ///
-    /// If Redis operations fail, the store falls back to allowing requests
-    /// to prevent service disruption.
+    /// If Redis operations fail, the error is returned to the middleware, which
+    /// allows the request to prevent service disruption.
+    ///
+    /// No timeouts are set by default, so a hung server blocks requests. Set them on a
+    /// [`RedisStoreBuilder`] before connecting; an operation timing out fails like any
+    /// other.
     pub struct RedisStore {
         /// Redis client for database operations
-        client: Client,
+        backend: Backend,
         /// Key prefix for namespacing rate limit data
         prefix: String,
+        /// Placement of hash tags in keys
+        layout: KeyLayout,
+        /// Timeouts applied to every connection
+        timeouts: Timeouts,
+        /// Lua script performing the sliding window check
+        script: Script,
+        /// Lua script removing a previously recorded request
+        refund_script: Script,
+        /// Lua script taking an in-flight permit
+        acquire_script: Script,
+        /// Random per-instance value keeping sorted set members unique across instances
+        nonce: u64,
+        /// Per-instance request counter keeping sorted set members unique within an instance
+        seq: AtomicU64,
+    }
+
+    /// Timeouts of connections, `None` waiting indefinitely.
+    #[derive(Default)]
+    struct Timeouts {
+        connect: Option<Duration>,
+        read: Option<Duration>,
+        write: Option<Duration>,
+    }
+
+    /// Opens a connection to a single node with the given timeouts.
+    fn connect(client: &Client, timeouts: &Timeouts) -> RedisResult<redis::Connection> {
+        let conn = match timeouts.connect {
+            Some(timeout) => client.get_connection_with_timeout(timeout)?,
+            None => client.get_connection()?,
+        };
+        conn.set_read_timeout(timeouts.read)?;
+        conn.set_write_timeout(timeouts.write)?;
+        Ok(conn)
+    }
+
+    /// Deployment the store connects to.
+    enum Backend {
+        Single(Client),
+        #[cfg(feature = "redis-cluster")]
+        Cluster(cluster::ClusterBackend),
+        #[cfg(feature = "redis-sentinel")]
+        Sentinel(sentinel::SentinelBackend),
+    }
+
+    impl Backend {
+        /// Opens a connection, following MOVED and ASK redirects on a cluster and
+        /// connecting to the primary behind Sentinel.
+        fn connection(&self, timeouts: &Timeouts) -> RedisResult<Box<dyn ConnectionLike + '_>> {
+            Ok(match self {
+                Backend::Single(client) => Box::new(connect(client, timeouts)?),
+                #[cfg(feature = "redis-cluster")]
+                Backend::Cluster(cluster) => Box::new(cluster.connection(timeouts)?),
+                #[cfg(feature = "redis-sentinel")]
+                Backend::Sentinel(sentinel) => Box::new(sentinel.connection(timeouts)?),
+            })
+        }
+    }
+
+    #[cfg(feature = "redis-cluster")]
+    mod cluster {
+        use redis::{
+            ConnectionLike, RedisResult, Value,
+            cluster::{ClusterClient, ClusterConnection},
+        };
+        use std::sync::{Mutex, PoisonError};
+
+        use super::Timeouts;
+
+        /// Cluster client along with idle connections kept for later operations, so that
+        /// the topology is only discovered again after a connection fails.
+        pub(super) struct ClusterBackend {
+            client: ClusterClient,
+            idle: Mutex<Vec<ClusterConnection>>,
+        }
+
+        impl ClusterBackend {
+            pub(super) fn new(client: ClusterClient) -> Self {
+                Self {
+                    client,
+                    idle: Mutex::new(Vec::new()),
+                }
+            }
+
+            /// Takes an idle connection, or opens one if all are in use.
+            pub(super) fn connection(
+                &self,
+                timeouts: &Timeouts,
+            ) -> RedisResult<PooledConnection<'_>> {
+                let idle = self
+                    .idle
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .pop();
+                let conn = match idle {
+                    Some(conn) => conn,
+                    None => {
+                        let conn = self.client.get_connection()?;
+                        conn.set_read_timeout(timeouts.read)?;
+                        conn.set_write_timeout(timeouts.write)?;
+                        conn
+                    }
+                };
+                Ok(PooledConnection {
+                    conn: Some(conn),
+                    backend: self,
+                })
+            }
+        }
+
+        /// Connection returned to the idle ones when dropped, unless it failed in a way
+        /// that requires reconnecting.
+        pub(super) struct PooledConnection<'a> {
+            conn: Option<ClusterConnection>,
+            backend: &'a ClusterBackend,
+        }
+
+        impl PooledConnection<'_> {
+            fn conn(&mut self) -> &mut ClusterConnection {
+                self.conn.as_mut().expect("connection taken only on drop")
+            }
+
+            fn check<T>(&mut self, result: RedisResult<T>) -> RedisResult<T> {
+                if let Err(err) = &result
+                    && (err.is_unrecoverable_error() || err.is_timeout())
+                {
+                    self.conn = None;
+                }
+                result
+            }
+        }
+
+        impl Drop for PooledConnection<'_> {
+            fn drop(&mut self) {
+                if let Some(conn) = self.conn.take() {
+                    self.backend
+                        .idle
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .push(conn);
+                }
+            }
+        }
+
+        impl ConnectionLike for PooledConnection<'_> {
+            fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+                let result = self.conn().req_packed_command(cmd);
+                self.check(result)
+            }
+
+            fn req_packed_commands(
+                &mut self,
+                cmd: &[u8],
+                offset: usize,
+                count: usize,
+            ) -> RedisResult<Vec<Value>> {
+                let result = self.conn().req_packed_commands(cmd, offset, count);
+                self.check(result)
+            }
+
+            fn get_db(&self) -> i64 {
+                self.conn.as_ref().map_or(0, ConnectionLike::get_db)
+            }
+
+            fn check_connection(&mut self) -> bool {
+                self.conn
+                    .as_mut()
+                    .is_some_and(ConnectionLike::check_connection)
+            }
+
+            fn is_open(&self) -> bool {
+                self.conn.as_ref().is_some_and(ConnectionLike::is_open)
+            }
+        }
+    }
+
+    #[cfg(feature = "redis-sentinel")]
+    mod sentinel {
+        use redis::{
+            Client, Connection, ConnectionAddr, ConnectionInfo, ConnectionLike, ErrorKind,
+            RedisError, RedisResult, Role, TlsMode, Value, sentinel::SentinelNodeConnectionInfo,
+        };
+        use std::sync::{Mutex, PoisonError};
+
+        use super::{Timeouts, connect};
+
+        /// Primary discovered through Sentinel, cached until it fails.
+        pub(super) struct SentinelBackend {
+            master_name: String,
+            sentinels: Vec<Client>,
+            /// Credentials, database and TLS mode of the primary
+            node: SentinelNodeConnectionInfo,
+            primary: Mutex<Option<Client>>,
+        }
+
+        impl SentinelBackend {
+            pub(super) fn new(
+                master_name: &str,
+                sentinels: &[&str],
+                node: SentinelNodeConnectionInfo,
+            ) -> RedisResult<Self> {
+                if sentinels.is_empty() {
+                    return Err(RedisError::from((
+                        ErrorKind::EmptySentinelList,
+                        "No sentinel given",
+                    )));
+                }
+                Ok(Self {
+                    master_name: master_name.to_string(),
+                    sentinels: sentinels
+                        .iter()
+                        .map(|url| Client::open(*url))
+                        .collect::<RedisResult<_>>()?,
+                    node,
+                    primary: Mutex::new(None),
+                })
+            }
+
+            /// Connects to the cached primary, asking the sentinels for it if there is
+            /// none or it cannot be reached.
+            pub(super) fn connection(
+                &self,
+                timeouts: &Timeouts,
+            ) -> RedisResult<PrimaryConnection<'_>> {
+                let cached = self
+                    .primary
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .clone();
+                let conn = match cached.map(|client| connect(&client, timeouts)) {
+                    Some(Ok(conn)) => conn,
+                    _ => {
+                        let (client, conn) = self.discover(timeouts)?;
+                        *self.primary.lock().unwrap_or_else(PoisonError::into_inner) = Some(client);
+                        conn
+                    }
+                };
+                Ok(PrimaryConnection {
+                    conn,
+                    backend: self,
+                })
+            }
+
+            /// Asks the sentinels in turn for the primary, connecting with the store's
+            /// timeouts so that an unreachable sentinel does not block the request.
+            fn discover(&self, timeouts: &Timeouts) -> RedisResult<(Client, Connection)> {
+                let mut last_err = None;
+                for sentinel in &self.sentinels {
+                    match self.ask(sentinel, timeouts) {
+                        Ok(found) => return Ok(found),
+                        Err(err) => last_err = Some(err),
+                    }
+                }
+                Err(last_err.expect("at least one sentinel"))
+            }
+
+            /// Looks up the primary on one sentinel and checks that it is still a primary.
+            fn ask(
+                &self,
+                sentinel: &Client,
+                timeouts: &Timeouts,
+            ) -> RedisResult<(Client, Connection)> {
+                let mut conn = connect(sentinel, timeouts)?;
+                let addr: Option<(String, u16)> = redis::cmd("SENTINEL")
+                    .arg("get-master-addr-by-name")
+                    .arg(&self.master_name)
+                    .query(&mut conn)?;
+                let Some((host, port)) = addr else {
+                    return Err(RedisError::from((
+                        ErrorKind::MasterNameNotFoundBySentinel,
+                        "Master name not known by sentinel",
+                        self.master_name.clone(),
+                    )));
+                };
+
+                let addr = match self.node.tls_mode {
+                    None => ConnectionAddr::Tcp(host, port),
+                    Some(mode) => ConnectionAddr::TcpTls {
+                        host,
+                        port,
+                        insecure: mode == TlsMode::Insecure,
+                        tls_params: None,
+                    },
+                };
+                let client = Client::open(ConnectionInfo {
+                    addr,
+                    redis: self.node.redis_connection_info.clone().unwrap_or_default(),
+                })?;
+                let mut primary = connect(&client, timeouts)?;
+                // Sentinels keep reporting a demoted primary until they agree on a failover
+                match redis::cmd("ROLE").query(&mut primary)? {
+                    Role::Primary { .. } => Ok((client, primary)),
+                    _ => Err(RedisError::from((
+                        ErrorKind::ReadOnly,
+                        "Node reported by sentinel is not a primary",
+                    ))),
+                }
+            }
+
+            fn forget_primary(&self) {
+                *self.primary.lock().unwrap_or_else(PoisonError::into_inner) = None;
+            }
+        }
+
+        /// Connection to the primary, forgetting it once it fails or has been demoted.
+        pub(super) struct PrimaryConnection<'a> {
+            conn: Connection,
+            backend: &'a SentinelBackend,
+        }
+
+        impl PrimaryConnection<'_> {
+            fn check<T>(&self, result: RedisResult<T>) -> RedisResult<T> {
+                if let Err(err) = &result
+                    && (err.is_io_error()
+                        || err.kind() == ErrorKind::ReadOnly
+                        // Older servers wrap the error of a write made by a script
+                        || err.to_string().contains("READONLY"))
+                {
+                    self.backend.forget_primary();
+                }
+                result
+            }
+        }
+
+        impl ConnectionLike for PrimaryConnection<'_> {
+            fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+                let result = self.conn.req_packed_command(cmd);
+                self.check(result)
+            }
+
+            fn req_packed_commands(
+                &mut self,
+                cmd: &[u8],
+                offset: usize,
+                count: usize,
+            ) -> RedisResult<Vec<Value>> {
+                let result = self.conn.req_packed_commands(cmd, offset, count);
+                self.check(result)
+            }
+
+            fn get_db(&self) -> i64 {
+                self.conn.get_db()
+            }
+
+            fn check_connection(&mut self) -> bool {
+                self.conn.check_connection()
+            }
+
+            fn is_open(&self) -> bool {
+                self.conn.is_open()
+            }
+        }
+    }
+
+    /// Hash tags of the keys, deciding which cluster slot they land in.
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum KeyLayout {
+        /// `{prefix}{key}`, no hash tag on a single node
+        Plain,
+        /// `{prefix}{{key}}`, each client in its own slot
+        ClientSlot,
+        /// `{{prefix}}{key}`, all clients in the same slot
+        SharedSlot,
+    }
+
+    /// Deployment a [`RedisStoreBuilder`] connects to.
+    enum Target {
+        Single(String),
+        #[cfg(feature = "redis-cluster")]
+        Cluster(Vec<String>),
+        #[cfg(feature = "redis-sentinel")]
+        Sentinel {
+            master_name: String,
+            sentinels: Vec<String>,
+            node: redis::sentinel::SentinelNodeConnectionInfo,
+        },
+    }
+
+    /// Builder of a [`RedisStore`], created by [`RedisStore::builder`],
+    /// [`RedisStore::cluster_builder`] or [`RedisStore::sentinel_builder`].
+    ///
+    /// The timeouts apply from the first connection on, including the one testing the
+    /// server in [`RedisStoreBuilder::build`].
+    pub struct RedisStoreBuilder {
+        target: Target,
+        timeouts: Timeouts,
+    }
+
+    impl RedisStoreBuilder {
+        fn new(target: Target) -> Self {
+            Self {
+                target,
+                timeouts: Timeouts::default(),
+            }
+        }
+
+        /// Sets how long to wait for a connection to be established.
+        pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+            self.timeouts.connect = Some(timeout);
+            self
+        }
+
+        /// Sets how long to wait for the reply of a command.
+        pub fn read_timeout(mut self, timeout: Duration) -> Self {
+            self.timeouts.read = Some(timeout);
+            self
+        }
+
+        /// Sets how long to wait for a command to be sent.
+        pub fn write_timeout(mut self, timeout: Duration) -> Self {
+            self.timeouts.write = Some(timeout);
+            self
+        }
+
+        /// Sets the credentials, database and TLS mode used to connect to the primary found
+        /// through Sentinel (requires `redis-sentinel` feature).
+        ///
+        /// Sentinels only report the address of the primary, so without this the primary
+        /// is reached over plain TCP, without authentication, on database 0. The sentinel
+        /// URLs carry their own settings. Has no effect outside of Sentinel.
+        ///
+        /// # Example
+        ///
+        /// ```rust,no_run
+        /// # #[cfg(feature = "redis-sentinel")]
+        /// # {
+        /// use actix_web_ratelimit::store::RedisStore;
+        /// use redis::{RedisConnectionInfo, sentinel::SentinelNodeConnectionInfo};
+        ///
+        /// let store = RedisStore::sentinel_builder("mymaster", &["redis://10.0.0.1:26379/"])
+        ///     .node_connection_info(SentinelNodeConnectionInfo {
+        ///         tls_mode: None,
+        ///         redis_connection_info: Some(RedisConnectionInfo {
+        ///             db: 1,
+        ///             password: Some("secret".to_string()),
+        ///             ..Default::default()
+        ///         }),
+        ///     })
+        ///     .build()?;
+        /// # }
+        /// # Ok::<(), redis::RedisError>(())
+        /// ```
+        #[cfg(feature = "redis-sentinel")]
+        pub fn node_connection_info(
+            mut self,
+            info: redis::sentinel::SentinelNodeConnectionInfo,
+        ) -> Self {
+            if let Target::Sentinel { node, .. } = &mut self.target {
+                *node = info;
+            }
+            self
+        }
+
+        /// Connects, tests the connection and creates the [`RedisStore`].
+        ///
+        /// # Errors
+        ///
+        /// Returns [`RedisError`] if a URL is invalid or the server cannot be reached
+        /// within the timeouts.
+        pub fn build(self) -> Result<RedisStore, RedisError> {
+            let timeouts = self.timeouts;
+            let (backend, layout) = match self.target {
+                Target::Single(url) => {
+                    let client = Client::open(url.as_str())?;
+                    let mut conn = connect(&client, &timeouts)?;
+                    let _: RedisResult<()> = redis::cmd("PING").query(&mut conn);
+                    (Backend::Single(client), KeyLayout::Plain)
+                }
+                #[cfg(feature = "redis-cluster")]
+                Target::Cluster(nodes) => {
+                    let mut builder = redis::cluster::ClusterClient::builder(nodes);
+                    if let Some(timeout) = timeouts.connect {
+                        builder = builder.connection_timeout(timeout);
+                    }
+                    if let Some(timeout) = timeouts.read {
+                        builder = builder.response_timeout(timeout);
+                    }
+                    let cluster = cluster::ClusterBackend::new(builder.build()?);
+                    let mut conn = cluster.connection(&timeouts)?;
+                    let _: RedisResult<()> = redis::cmd("PING").query(&mut conn);
+                    drop(conn);
+                    (Backend::Cluster(cluster), KeyLayout::ClientSlot)
+                }
+                #[cfg(feature = "redis-sentinel")]
+                Target::Sentinel {
+                    master_name,
+                    sentinels,
+                    node,
+                } => {
+                    let sentinels: Vec<&str> = sentinels.iter().map(String::as_str).collect();
+                    let sentinel = sentinel::SentinelBackend::new(&master_name, &sentinels, node)?;
+                    let mut conn = sentinel.connection(&timeouts)?;
+                    let _: RedisResult<()> = redis::cmd("PING").query(&mut conn);
+                    drop(conn);
+                    (Backend::Sentinel(sentinel), KeyLayout::Plain)
+                }
+            };
+            Ok(RedisStore::with_backend(backend, layout, timeouts))
+        }
     }
 
     impl RedisStore {
@@ -73,16 +717,139 @@ mod redis_store_impl {
         /// - Cannot connect to Redis server
         /// - PING command fails
         pub fn new(redis_url: &str) -> Result<Self, RedisError> {
-            let client = Client::open(redis_url)?;
-            let mut conn = client.get_connection()?;
-            let _: RedisResult<()> = redis::cmd("PING").query(&mut conn);
+            Self::builder(redis_url).build()
+        }
 
-            Ok(Self {
-                client,
-                prefix: REDIS_PREFIX.to_string(),
+        /// Starts building a [`RedisStore`] connecting to a single node, to set timeouts
+        /// before the connection is tested.
+        ///
+        /// # Example
+        ///
+        /// ```rust,no_run
+        /// # #[cfg(feature = "redis")]
+        /// # {
+        /// use actix_web_ratelimit::store::RedisStore;
+        /// use std::time::Duration;
+        ///
+        /// let store = RedisStore::builder("redis://127.0.0.1/")
+        ///     .connect_timeout(Duration::from_millis(200))
+        ///     .read_timeout(Duration::from_millis(100))
+        ///     .write_timeout(Duration::from_millis(100))
+        ///     .build()?;
+        /// # }
+        /// # Ok::<(), redis::RedisError>(())
+        /// ```
+        pub fn builder(redis_url: &str) -> RedisStoreBuilder {
+            RedisStoreBuilder::new(Target::Single(redis_url.to_string()))
+        }
+
+        /// Creates a new [`RedisStore`] backed by a Redis Cluster (requires `redis-cluster`
+        /// feature) and tests the connection.
+        ///
+        /// The cluster topology is discovered from the given nodes, and MOVED and ASK
+        /// redirects are followed transparently.
+        ///
+        /// # Arguments
+        ///
+        /// * `nodes` - URLs of some of the cluster nodes
+        ///
+        /// # Example
+        ///
+        /// ```rust,no_run
+        /// # #[cfg(feature = "redis-cluster")]
+        /// # {
+        /// use actix_web_ratelimit::store::RedisStore;
+        ///
+        /// let store = RedisStore::new_cluster(&[
+        ///     "redis://10.0.0.1:6379/",
+        ///     "redis://10.0.0.2:6379/",
+        ///     "redis://10.0.0.3:6379/",
+        /// ])?;
+        /// # }
+        /// # Ok::<(), redis::RedisError>(())
+        /// ```
+        ///
+        /// # Errors
+        ///
+        /// Returns [`RedisError`] if a URL is invalid or no node can be reached.
+        #[cfg(feature = "redis-cluster")]
+        pub fn new_cluster(nodes: &[&str]) -> Result<Self, RedisError> {
+            Self::cluster_builder(nodes).build()
+        }
+
+        /// Starts building a [`RedisStore`] backed by a Redis Cluster (requires
+        /// `redis-cluster` feature), see [`RedisStore::new_cluster`].
+        #[cfg(feature = "redis-cluster")]
+        pub fn cluster_builder(nodes: &[&str]) -> RedisStoreBuilder {
+            RedisStoreBuilder::new(Target::Cluster(
+                nodes.iter().map(|node| node.to_string()).collect(),
+            ))
+        }
+
+        /// Creates a new [`RedisStore`] writing to the primary of a Sentinel-managed
+        /// deployment (requires `redis-sentinel` feature) and tests the connection.
+        ///
+        /// The primary is rediscovered through the sentinels after a failover, see
+        /// [Redis Sentinel](RedisStore#redis-sentinel). The primary is reached without
+        /// credentials; use [`RedisStore::sentinel_builder`] with
+        /// [`RedisStoreBuilder::node_connection_info`] if it requires them.
+        ///
+        /// # Arguments
+        ///
+        /// * `master_name` - Name of the monitored master, as configured in Sentinel
+        /// * `sentinels` - URLs of the sentinels
+        ///
+        /// # Example
+        ///
+        /// ```rust,no_run
+        /// # #[cfg(feature = "redis-sentinel")]
+        /// # {
+        /// use actix_web_ratelimit::store::RedisStore;
+        ///
+        /// let store = RedisStore::new_sentinel(
+        ///     "mymaster",
+        ///     &["redis://10.0.0.1:26379/", "redis://10.0.0.2:26379/"],
+        /// )?;
+        /// # }
+        /// # Ok::<(), redis::RedisError>(())
+        /// ```
+        ///
+        /// # Errors
+        ///
+        /// Returns [`RedisError`] if a URL is invalid or no sentinel knows a reachable
+        /// primary.
+        #[cfg(feature = "redis-sentinel")]
+        pub fn new_sentinel(master_name: &str, sentinels: &[&str]) -> Result<Self, RedisError> {
+            Self::sentinel_builder(master_name, sentinels).build()
+        }
+
+        /// Starts building a [`RedisStore`] writing to the primary of a Sentinel-managed
+        /// deployment (requires `redis-sentinel` feature), see [`RedisStore::new_sentinel`].
+        ///
+        /// The timeouts also apply to the sentinels while the primary is looked up.
+        #[cfg(feature = "redis-sentinel")]
+        pub fn sentinel_builder(master_name: &str, sentinels: &[&str]) -> RedisStoreBuilder {
+            RedisStoreBuilder::new(Target::Sentinel {
+                master_name: master_name.to_string(),
+                sentinels: sentinels.iter().map(|url| url.to_string()).collect(),
+                node: Default::default(),
             })
         }
 
+        fn with_backend(backend: Backend, layout: KeyLayout, timeouts: Timeouts) -> Self {
+            Self {
+                backend,
+                prefix: REDIS_PREFIX.to_string(),
+                layout,
+                timeouts,
+                script: Script::new(CHECK_SCRIPT),
+                refund_script: Script::new(REFUND_SCRIPT),
+                acquire_script: Script::new(ACQUIRE_SCRIPT),
+                nonce: RandomState::new().hash_one(std::process::id()),
+                seq: AtomicU64::new(0),
+            }
+        }
+
         /// Sets a custom prefix for Redis keys.
         ///
         /// This is useful for namespacing when multiple applications
@@ -109,6 +876,19 @@ mod redis_store_impl {
             self
         }
 
+        /// Places the keys of all clients in the same cluster slot by hash tagging the
+        /// prefix instead of the client identifier.
+        ///
+        /// A per-client and a global limit are then checked in one atomic script, at the
+        /// cost of sending all rate limiting traffic to a single shard. Has no effect
+        /// outside of a cluster.
+        pub fn with_shared_slot(mut self) -> Self {
+            if self.layout == KeyLayout::ClientSlot {
+                self.layout = KeyLayout::SharedSlot;
+            }
+            self
+        }
+
         /// Generates the full Redis key by combining prefix and client identifier.
         ///
         /// # Arguments
@@ -119,128 +899,480 @@ mod redis_store_impl {
         ///
         /// Full Redis key string
         fn get_key(&self, key: &str) -> String {
-            format!("{}{}", self.prefix, key)
+            match self.layout {
+                KeyLayout::Plain => format!("{}{}", self.prefix, key),
+                KeyLayout::ClientSlot => format!("{}{{{}}}", self.prefix, key),
+                KeyLayout::SharedSlot => format!("{{{}}}{}", self.prefix, key),
+            }
         }
+
+        /// Generates the Redis key holding the client's in-flight leases.
+        fn get_inflight_key(&self, key: &str) -> String {
+            format!("{}{}", self.get_key(key), INFLIGHT_SUFFIX)
+        }
+
+        /// Generates the Redis key marking the client as banned.
+        fn get_ban_key(&self, key: &str) -> String {
+            format!("{}{}", self.get_key(key), BAN_SUFFIX)
+        }
+
+        /// Collects the identifiers of all clients starting with `client_prefix` that have
+        /// a sorted set under the store prefix.
+        ///
+        /// Uses `SCAN` rather than `KEYS`, so Redis is never blocked for long, but still
+        /// walks the whole keyspace.
+        fn scan_clients(
+            &self,
+            conn: &mut dyn ConnectionLike,
+            client_prefix: &str,
+        ) -> StoreResult<Vec<String>> {
+            // A cluster would have to be scanned node by node
+            if self.layout != KeyLayout::Plain {
+                return Err(StoreError::Unsupported("scan on Redis Cluster"));
+            }
+            let pattern = format!(
+                "{}{}*",
+                escape_glob(&self.prefix),
+                escape_glob(client_prefix)
+            );
+            let mut clients = Vec::new();
+            let mut cursor = 0u64;
+            loop {
+                let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                    .arg(cursor)
+                    .arg("MATCH")
+                    .arg(&pattern)
+                    .arg("COUNT")
+                    .arg(100)
+                    .query(conn)?;
+                clients.extend(
+                    keys.into_iter()
+                        .filter(|k| !k.ends_with(INFLIGHT_SUFFIX) && !k.ends_with(BAN_SUFFIX))
+                        .filter_map(|k| k.strip_prefix(&self.prefix).map(str::to_string)),
+                );
+                if next == 0 {
+                    // SCAN may return a key more than once
+                    clients.sort_unstable();
+                    clients.dedup();
+                    return Ok(clients);
+                }
+                cursor = next;
+            }
+        }
+
+        /// Runs [`CHECK_SCRIPT`] over the given keys, recording the request only if `record` is set.
+        fn run_checks(
+            &self,
+            checks: &[(&str, &RateLimitConfig)],
+            cost: usize,
+            record: bool,
+        ) -> StoreResult<RateLimitState> {
+            let mut conn = self.backend.connection(&self.timeouts)?;
+
+            let cmd = self.check_cmd(checks, cost, record);
+            let values: Vec<u64> = match cmd.query(&mut *conn) {
+                Err(err) if err.kind() == ErrorKind::NoScriptError => {
+                    self.script.load(&mut *conn)?;
+                    cmd.query(&mut *conn)?
+                }
+                result => result?,
+            };
+            Ok(Self::fold_states(checks, cost, record, &values))
+        }
+
+        /// Sends the checks of independent requests as one pipeline of scripts.
+        fn pipeline_checks(
+            &self,
+            checks: &[(&str, usize, &RateLimitConfig)],
+            record: bool,
+        ) -> RedisResult<Vec<StoreResult<RateLimitState>>> {
+            let mut conn = self.backend.connection(&self.timeouts)?;
+
+            let cmds: Vec<_> = checks
+                .iter()
+                .map(|&(key, cost, config)| self.check_cmd(&[(key, config)], cost, record))
+                .collect();
+            let send = |conn: &mut dyn ConnectionLike, indices: &[usize]| {
+                let mut pipe = redis::pipe();
+                for &i in indices {
+                    pipe.add_command(cmds[i].clone());
+                }
+                conn.req_packed_commands(&pipe.get_packed_pipeline(), 0, indices.len())
+            };
+
+            let mut replies = send(&mut *conn, &(0..checks.len()).collect::<Vec<_>>())?;
+            // Only the scripts that did not run are sent again once the script is loaded
+            let missing: Vec<usize> = (0..replies.len())
+                .filter(
+                    |&i| matches!(&replies[i], Value::ServerError(err) if err.code() == "NOSCRIPT"),
+                )
+                .collect();
+            if !missing.is_empty() {
+                self.script.load(&mut *conn)?;
+                for (i, reply) in missing.iter().zip(send(&mut *conn, &missing)?) {
+                    replies[*i] = reply;
+                }
+            }
+
+            Ok(replies
+                .into_iter()
+                .zip(checks)
+                .map(|(reply, &(key, cost, config))| {
+                    let values: Vec<u64> = redis::from_owned_redis_value(reply.extract_error()?)?;
+                    Ok(Self::fold_states(&[(key, config)], cost, record, &values))
+                })
+                .collect())
+        }
+
+        /// Sends the ban lookups of several clients as one pipeline.
+        fn pipeline_bans(&self, keys: &[&str]) -> RedisResult<Vec<StoreResult<bool>>> {
+            let mut conn = self.backend.connection(&self.timeouts)?;
+
+            let mut pipe = redis::pipe();
+            for key in keys {
+                pipe.cmd("EXISTS").arg(self.get_ban_key(key));
+            }
+            let replies = conn.req_packed_commands(&pipe.get_packed_pipeline(), 0, keys.len())?;
+            Ok(replies
+                .into_iter()
+                .map(|reply| Ok(redis::from_owned_redis_value(reply.extract_error()?)?))
+                .collect())
+        }
+
+        /// Builds the call of the check script over `checks`.
+        fn check_cmd(
+            &self,
+            checks: &[(&str, &RateLimitConfig)],
+            cost: usize,
+            record: bool,
+        ) -> redis::Cmd {
+            let now = chrono::Utc::now().timestamp_millis();
+            let member = format!(
+                "{}:{:x}-{}:{}",
+                now,
+                self.nonce,
+                self.seq.fetch_add(1, Ordering::Relaxed),
+                cost
+            );
+
+            let mut cmd = redis::cmd("EVALSHA");
+            cmd.arg(self.script.get_hash()).arg(checks.len());
+            for &(key, _) in checks {
+                cmd.arg(self.get_key(key));
+            }
+            cmd.arg(now).arg(cost).arg(member).arg(record as u8);
+            for &(key, config) in checks {
+                debug!(
+                    "Checking rate limit for key: {} with config: max_req={}, window={:?}, cost={}",
+                    key, config.max_requests, config.window_secs, cost
+                );
+                cmd.arg(window_ms(config)).arg(config.max_requests);
+            }
+            cmd
+        }
+
+        /// Combines the states returned by the check script into the most restrictive.
+        fn fold_states(
+            checks: &[(&str, &RateLimitConfig)],
+            cost: usize,
+            record: bool,
+            values: &[u64],
+        ) -> RateLimitState {
+            let mut result = RateLimitState::UNLIMITED;
+            for (&(key, config), state) in checks.iter().zip(values.chunks_exact(5)) {
+                let state = RateLimitState {
+                    limited: state[0] == 1,
+                    used: state[1] as usize,
+                    remaining: state[2] as usize,
+                    retry_after: Duration::from_millis(state[3]),
+                    reset_after: Duration::from_millis(state[4]),
+                };
+                if record && state.limited {
+                    warn!(
+                        "Rate limit exceeded for key({}): used({}) + cost({}) > max_req({})",
+                        key, state.used, cost, config.max_requests
+                    );
+                }
+                result = result.most_restrictive(state);
+            }
+            result
+        }
+    }
+
+    /// Window of the limit in milliseconds, the precision of sorted set scores.
+    ///
+    /// At least 1ms, so sub-millisecond windows still expire their keys.
+    fn window_ms(config: &RateLimitConfig) -> u64 {
+        (config.window_secs.as_millis() as u64).max(1)
+    }
+
+    /// Escapes the characters `SCAN MATCH` treats as glob patterns.
+    fn escape_glob(s: &str) -> String {
+        let mut escaped = String::with_capacity(s.len());
+        for c in s.chars() {
+            if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    }
+
+    /// Results of a batch whose pipeline failed as a whole: every entry failed the same
+    /// way, the first one keeping the original error.
+    fn pipeline_failed<T>(err: RedisError, len: usize) -> Vec<StoreResult<T>> {
+        let (kind, detail) = (err.kind(), err.to_string());
+        let mut results = vec![Err(err.into())];
+        results.extend(
+            (1..len)
+                .map(|_| Err(RedisError::from((kind, "Pipeline failed", detail.clone())).into())),
+        );
+        results.truncate(len);
+        results
     }
 
     impl RateLimitStore for RedisStore {
         /// Checks if the client has exceeded the rate limit using Redis Sorted Sets.
         ///
-        /// This method implements a distributed sliding window algorithm:
+        /// This method implements a distributed sliding window algorithm in one Lua script:
         /// 1. Removes expired request timestamps from the sorted set
-        /// 2. Counts remaining requests in the time window
-        /// 3. Checks if count exceeds the configured limit
-        /// 4. If not exceeded, adds current timestamp to the set
-        /// 5. Sets expiration time for automatic cleanup
-        ///
-        /// # Fallback Strategy
-        ///
-        /// If any Redis operation fails, the method returns `false` (allow request)
-        /// to prevent service disruption. Errors are logged for monitoring.
+        /// 2. Sums the cost of the remaining requests in the time window
+        /// 3. Checks if the sum plus the request cost exceeds the configured limit
+        /// 4. If not exceeded, adds the current request with its cost to the set
+        /// 5. Sets the key to expire once the window has passed
         ///
         /// # Arguments
         ///
         /// * `key` - Client identifier (typically IP address)
+        /// * `cost` - Number of units this request consumes
         /// * `config` - Rate limiting configuration
         ///
-        /// # Returns
+        /// # Errors
         ///
-        /// `true` if the client has exceeded the rate limit, `false` otherwise
-        fn is_limited(&self, key: &str, config: &RateLimitConfig) -> bool {
-            use std::i32;
+        /// Returns [`crate::store::StoreError::Redis`] if the connection or the script fails.
+        fn check(
+            &self,
+            key: &str,
+            cost: usize,
+            config: &RateLimitConfig,
+        ) -> StoreResult<RateLimitState> {
+            self.run_checks(&[(key, config)], cost, true)
+        }
 
-            let redis_key = self.get_key(key);
+        fn backend(&self) -> &'static str {
+            "redis"
+        }
 
-            debug!(
-                "Checking rate limit for key: {} with config: max_req={}, window={:?}",
-                key, config.max_requests, config.window_secs
-            );
+        /// Runs the same script as [`RedisStore::check`] without adding the request.
+        fn peek(
+            &self,
+            key: &str,
+            cost: usize,
+            config: &RateLimitConfig,
+        ) -> StoreResult<RateLimitState> {
+            self.run_checks(&[(key, config)], cost, false)
+        }
 
-            let mut conn = match self.client.get_connection() {
-                Ok(conn) => conn,
-                Err(err) => {
-                    error!("Failed to get Redis connection: {}", err);
-                    // Fallback: allow request when connection fails (graceful degradation)
-                    return false;
-                }
-            };
+        /// Checks all keys in a single Lua script, so the request is recorded atomically in
+        /// every sorted set or in none of them.
+        ///
+        /// On a cluster without [`RedisStore::with_shared_slot`], the keys are checked one
+        /// after the other instead.
+        fn check_all(
+            &self,
+            checks: &[(&str, &RateLimitConfig)],
+            cost: usize,
+        ) -> StoreResult<RateLimitState> {
+            // Keys of different clients may live in different cluster slots
+            if self.layout == KeyLayout::ClientSlot && checks.len() > 1 {
+                return check_each(self, checks, cost);
+            }
+            self.run_checks(checks, cost, true)
+        }
 
-            // Use Redis Sorted Set to store request timestamps
-            let now = chrono::Utc::now().timestamp_millis() as f64;
-            let window_start = now - config.window_secs.as_millis() as f64;
-
-            // Step 1: Remove expired requests outside the time window
-            let remove_result: redis::RedisResult<i32> = redis::cmd("ZREMRANGEBYSCORE")
-                .arg(&redis_key)
-                .arg("-inf")
-                .arg(window_start)
-                .query(&mut conn);
-
-            if let Err(err) = remove_result {
-                error!("Failed to remove old entries: {}", err);
-            }
-
-            // Step 2: Count current requests within the time window
-            let count_result: redis::RedisResult<usize> = redis::cmd("ZCOUNT")
-                .arg(&redis_key)
-                .arg(window_start)
-                .arg("+inf")
-                .query(&mut conn);
-
-            let count = match count_result {
-                Ok(c) => c,
-                Err(err) => {
-                    error!("Redis error on ZCOUNT: {}", err);
-                    // Fallback: allow request when count fails (graceful degradation)
-                    return false;
-                }
-            };
+        /// Sends all checks to Redis in one pipeline, each recorded independently.
+        ///
+        /// On a cluster without [`RedisStore::with_shared_slot`], the keys are checked one
+        /// after the other instead.
+        fn check_batch(
+            &self,
+            checks: &[(&str, usize, &RateLimitConfig)],
+        ) -> Vec<StoreResult<RateLimitState>> {
+            if self.layout == KeyLayout::ClientSlot {
+                return checks
+                    .iter()
+                    .map(|&(key, cost, config)| self.check(key, cost, config))
+                    .collect();
+            }
+            self.pipeline_checks(checks, true)
+                .unwrap_or_else(|err| pipeline_failed(err, checks.len()))
+        }
 
-            if count > config.max_requests {
-                warn!(
-                    "Rate limit exceeded for key({}): count({}) > max_req({})",
-                    key, count, config.max_requests
-                );
-                return true;
+        /// Peeks at all clients in one pipeline.
+        ///
+        /// On a cluster without [`RedisStore::with_shared_slot`], the keys are peeked at
+        /// one after the other instead.
+        fn peek_batch(
+            &self,
+            checks: &[(&str, usize, &RateLimitConfig)],
+        ) -> Vec<StoreResult<RateLimitState>> {
+            if self.layout == KeyLayout::ClientSlot {
+                return checks
+                    .iter()
+                    .map(|&(key, cost, config)| self.peek(key, cost, config))
+                    .collect();
+            }
+            self.pipeline_checks(checks, false)
+                .unwrap_or_else(|err| pipeline_failed(err, checks.len()))
+        }
+
+        /// Removes the most recent request of `cost` units from the sorted set.
+        fn refund(&self, key: &str, cost: usize, _config: &RateLimitConfig) -> StoreResult<()> {
+            let mut conn = self.backend.connection(&self.timeouts)?;
+            let _: i64 = self
+                .refund_script
+                .key(self.get_key(key))
+                .arg(cost)
+                .invoke(&mut *conn)?;
+            Ok(())
+        }
+
+        /// Deletes the client's sorted set.
+        fn reset(&self, key: &str) -> StoreResult<()> {
+            let mut conn = self.backend.connection(&self.timeouts)?;
+            redis::cmd("DEL").arg(self.get_key(key)).exec(&mut *conn)?;
+            Ok(())
+        }
+
+        /// Sets the client's ban key, expiring after `duration` if given.
+        fn ban(&self, key: &str, duration: Option<Duration>) -> StoreResult<()> {
+            let mut conn = self.backend.connection(&self.timeouts)?;
+            let mut cmd = redis::cmd("SET");
+            cmd.arg(self.get_ban_key(key)).arg(1);
+            if let Some(duration) = duration {
+                cmd.arg("PX").arg(duration.as_millis().max(1) as u64);
             }
+            cmd.exec(&mut *conn)?;
+            Ok(())
+        }
+
+        fn unban(&self, key: &str) -> StoreResult<()> {
+            let mut conn = self.backend.connection(&self.timeouts)?;
+            redis::cmd("DEL")
+                .arg(self.get_ban_key(key))
+                .exec(&mut *conn)?;
+            Ok(())
+        }
 
-            // Step 3: Add current request timestamp
-            let add_result: redis::RedisResult<()> = redis::cmd("ZADD")
-                .arg(&redis_key)
-                .arg(now)
-                .arg(now)
-                .query(&mut conn);
+        /// Checks whether the client's ban key exists.
+        ///
+        /// This costs one extra round trip per request.
+        fn is_banned(&self, key: &str) -> StoreResult<bool> {
+            let mut conn = self.backend.connection(&self.timeouts)?;
+            let banned: bool = redis::cmd("EXISTS")
+                .arg(self.get_ban_key(key))
+                .query(&mut *conn)?;
+            Ok(banned)
+        }
 
-            if let Err(err) = add_result {
-                error!("Failed to add new entry: {}", err);
+        /// Checks the ban keys of all clients in one pipeline.
+        ///
+        /// On a cluster without [`RedisStore::with_shared_slot`], the keys are checked one
+        /// after the other instead.
+        fn is_banned_batch(&self, keys: &[&str]) -> Vec<StoreResult<bool>> {
+            if self.layout == KeyLayout::ClientSlot {
+                return keys.iter().map(|key| self.is_banned(key)).collect();
             }
+            self.pipeline_bans(keys)
+                .unwrap_or_else(|err| pipeline_failed(err, keys.len()))
+        }
 
-            // Step 4: Set expiration time slightly longer than window for cleanup
-            let expiry = config.window_secs.as_secs() + 10;
-            let expire_result: redis::RedisResult<()> = redis::cmd("EXPIRE")
-                .arg(&redis_key)
-                .arg(expiry as i64)
-                .query(&mut conn);
+        /// Scans the sorted sets of clients starting with `prefix` and deletes them,
+        /// counting the sets that still existed.
+        ///
+        /// Deletion is not atomic: requests recorded while the scan runs may survive.
+        fn reset_prefix(&self, prefix: &str) -> StoreResult<usize> {
+            let mut conn = self.backend.connection(&self.timeouts)?;
+            let clients = self.scan_clients(&mut *conn, prefix)?;
+            let mut deleted = 0;
+            for batch in clients.chunks(100) {
+                let keys: Vec<String> = batch.iter().map(|c| self.get_key(c)).collect();
+                deleted += redis::cmd("DEL").arg(keys).query::<usize>(&mut *conn)?;
+            }
+            Ok(deleted)
+        }
 
-            if let Err(err) = expire_result {
-                error!("Failed to set expiry: {}", err);
+        /// Scans the sorted sets under the prefix and sums their unexpired costs.
+        ///
+        /// Walks the whole keyspace, so it is meant for occasional inspection rather than
+        /// the request path.
+        fn snapshot(&self, config: &RateLimitConfig) -> StoreResult<Vec<(String, usize)>> {
+            let mut conn = self.backend.connection(&self.timeouts)?;
+            let clients = self.scan_clients(&mut *conn, "")?;
+            if clients.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            // Same bound as the check script, which drops entries at or before it
+            let min = chrono::Utc::now().timestamp_millis() - window_ms(config) as i64;
+            let mut pipe = redis::pipe();
+            for client in &clients {
+                pipe.cmd("ZRANGEBYSCORE")
+                    .arg(self.get_key(client))
+                    .arg(format!("({}", min))
+                    .arg("+inf");
             }
+            let members: Vec<Vec<String>> = pipe.query(&mut *conn)?;
 
-            false
+            Ok(clients
+                .into_iter()
+                .zip(members)
+                .map(|(client, members)| {
+                    let used = members
+                        .iter()
+                        .filter_map(|m| m.rsplit(':').next()?.parse::<usize>().ok())
+                        .sum();
+                    (client, used)
+                })
+                .filter(|&(_, used)| used > 0)
+                .collect())
         }
     }
 
-    /// Implementation of [`RateLimitStore`] for `Arc<RedisStore>` to enable shared ownership.
-    ///
-    /// This allows the same `RedisStore` instance to be used across multiple threads
-    /// and middleware instances safely.
-    impl RateLimitStore for Arc<RedisStore> {
-        /// Delegates to the underlying `RedisStore` implementation.
-        fn is_limited(&self, key: &str, config: &RateLimitConfig) -> bool {
-            (**self).is_limited(key, config)
+    impl ConcurrencyStore for RedisStore {
+        /// Adds a lease to the client's in-flight set if it holds fewer than `max_concurrent`.
+        ///
+        /// Expired leases are removed first, so permits never released by a crashed
+        /// instance only block the client for `lease_secs`.
+        fn acquire(&self, key: &str, config: &ConcurrencyConfig) -> StoreResult<Option<u64>> {
+            let mut conn = self.backend.connection(&self.timeouts)?;
+            let lease = self.seq.fetch_add(1, Ordering::Relaxed);
+            let acquired: u8 = self
+                .acquire_script
+                .key(self.get_inflight_key(key))
+                .arg(chrono::Utc::now().timestamp_millis())
+                .arg(config.max_concurrent)
+                .arg(format!("{:x}-{}", self.nonce, lease))
+                .arg(config.lease_secs.as_millis() as u64)
+                .invoke(&mut *conn)?;
+            Ok((acquired == 1).then_some(lease))
+        }
+
+        /// Removes the lease from the client's in-flight set.
+        fn release(&self, key: &str, lease: u64) -> StoreResult<()> {
+            let mut conn = self.backend.connection(&self.timeouts)?;
+            redis::cmd("ZREM")
+                .arg(self.get_inflight_key(key))
+                .arg(format!("{:x}-{}", self.nonce, lease))
+                .exec(&mut *conn)?;
+            Ok(())
+        }
+
+        fn remote(&self) -> bool {
+            true
         }
     }
 }
 
 #[cfg(feature = "redis")]
-pub use redis_store_impl::RedisStore;
+pub use redis_store_impl::{RedisStore, RedisStoreBuilder};
@@ -0,0 +1,350 @@
+use dashmap::DashMap;
+use log::warn;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::{ConcurrencyConfig, RateLimitConfig},
+    store::{ConcurrencyStore, RateLimitState, RateLimitStore, StoreResult},
+};
+
+/// Number of shards of the maps by default
+const DEFAULT_SHARDS: usize = 64;
+
+/// Fixed window counter of a client, updated with compare-and-swap.
+///
+/// The index of the window is packed in the high 32 bits of `state` and the units used
+/// within it in the low 32 bits, so that both change in one atomic operation.
+struct Counter {
+    state: AtomicU64,
+    /// Length of the window in milliseconds, kept to purge the counter once it ends
+    window_ms: AtomicU64,
+}
+
+impl Counter {
+    fn unpack(state: u64) -> (u32, u64) {
+        ((state >> 32) as u32, state & u32::MAX as u64)
+    }
+
+    fn pack(index: u32, used: u64) -> u64 {
+        ((index as u64) << 32) | used.min(u32::MAX as u64)
+    }
+}
+
+/// Fixed window the current time falls in.
+struct Window {
+    /// Index of the window since the store was created, wrapping around
+    index: u32,
+    /// Time left until the end of the window
+    left: Duration,
+    length: Duration,
+}
+
+/// In-memory implementation of [`RateLimitStore`] tuned for hot keys and high request
+/// rates.
+///
+/// [`crate::store::MemoryStore`] keeps every request of the sliding window and locks the
+/// client's entry while pruning them, so a client with a large `max_requests` costs
+/// each request time proportional to the limit, and concurrent requests of that client
+/// queue up behind one another. This store counts requests in fixed windows instead: a
+/// check takes a shared lock on one shard of the map and updates the client's counter
+/// with a compare-and-swap, so its cost does not depend on the limit and concurrent
+/// requests of the same client never block each other.
+///
+/// The trade-off is the fixed window itself: windows start when the store is created,
+/// and at most twice the limit may pass around the boundary of two windows. Counters
+/// hold up to `u32::MAX` units per window, so larger limits are capped to that.
+///
+/// Counters of clients that stopped sending requests are kept until
+/// [`ShardedMemoryStore::purge_expired`] is called.
+///
+/// # Example
+///
+/// ```rust
+/// use actix_web_ratelimit::config::RateLimitConfig;
+/// use actix_web_ratelimit::store::{RateLimitStore, ShardedMemoryStore};
+/// use std::sync::Arc;
+///
+/// let store = Arc::new(ShardedMemoryStore::new().shards(128));
+/// let config = RateLimitConfig::default().max_requests(100_000);
+///
+/// assert_eq!(store.check("client", 1, &config).unwrap().remaining, 99_999);
+/// ```
+pub struct ShardedMemoryStore {
+    counters: DashMap<String, Counter>,
+    in_flight: DashMap<String, usize>,
+    bans: DashMap<String, Option<Instant>>,
+    /// Start of the first window
+    epoch: Instant,
+}
+
+impl ShardedMemoryStore {
+    /// Creates an empty store with 64 shards.
+    pub fn new() -> Self {
+        Self::with_shards(DEFAULT_SHARDS)
+    }
+
+    fn with_shards(shards: usize) -> Self {
+        // DashMap needs a power of two greater than one
+        let shards = shards.next_power_of_two().max(2);
+        Self {
+            counters: DashMap::with_shard_amount(shards),
+            in_flight: DashMap::with_shard_amount(shards),
+            bans: DashMap::with_shard_amount(shards),
+            epoch: Instant::now(),
+        }
+    }
+
+    /// Sets the number of shards of the maps, rounded up to a power of two.
+    ///
+    /// Clients in different shards never contend for the same lock. A few times the
+    /// number of worker threads is usually enough.
+    pub fn shards(self, shards: usize) -> Self {
+        Self::with_shards(shards)
+    }
+
+    /// Removes the counters of clients whose window has ended.
+    ///
+    /// Walks every shard, so it is meant to be called periodically rather than on the
+    /// request path.
+    ///
+    /// # Returns
+    ///
+    /// The number of counters removed.
+    pub fn purge_expired(&self) -> usize {
+        let elapsed = self.elapsed_ms();
+        let before = self.counters.len();
+        self.counters.retain(|_, counter| {
+            let window_ms = counter.window_ms.load(Ordering::Relaxed).max(1);
+            let (index, _) = Counter::unpack(counter.state.load(Ordering::Acquire));
+            index == (elapsed / window_ms) as u32
+        });
+        let now = Instant::now();
+        self.bans
+            .retain(|_, expires| expires.is_none_or(|t| t > now));
+        before.saturating_sub(self.counters.len())
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.epoch.elapsed().as_millis() as u64
+    }
+
+    fn window(&self, config: &RateLimitConfig) -> Window {
+        let length = (config.window_secs.as_millis() as u64).max(1);
+        let elapsed = self.elapsed_ms();
+        Window {
+            index: (elapsed / length) as u32,
+            left: Duration::from_millis(length - elapsed % length),
+            length: Duration::from_millis(length),
+        }
+    }
+
+    /// Limit of `config`, capped to the units a counter can hold.
+    fn max_requests(config: &RateLimitConfig) -> usize {
+        config.max_requests.min(u32::MAX as usize)
+    }
+
+    /// Units a counter holds within `window`.
+    fn used(state: u64, window: &Window) -> usize {
+        match Counter::unpack(state) {
+            (index, used) if index == window.index => used as usize,
+            _ => 0,
+        }
+    }
+
+    fn state(
+        used: usize,
+        cost: usize,
+        limited: bool,
+        window: &Window,
+        config: &RateLimitConfig,
+    ) -> RateLimitState {
+        let max_requests = Self::max_requests(config);
+        RateLimitState {
+            limited,
+            used,
+            remaining: max_requests.saturating_sub(used),
+            retry_after: match (limited, cost <= max_requests) {
+                (false, _) => Duration::ZERO,
+                (true, true) => window.left,
+                (true, false) => window.length,
+            },
+            reset_after: if used > 0 {
+                window.left
+            } else {
+                Duration::ZERO
+            },
+        }
+    }
+}
+
+/// Default implementation that creates a new [`ShardedMemoryStore`] instance.
+impl Default for ShardedMemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RateLimitStore for ShardedMemoryStore {
+    /// Adds `cost` to the client's counter if it fits within the limit.
+    ///
+    /// Only the first request of a client takes a write lock on its shard, to insert the
+    /// counter. Every later one reads it under a shared lock and retries the
+    /// compare-and-swap if another request updated it in the meantime.
+    fn check(
+        &self,
+        key: &str,
+        cost: usize,
+        config: &RateLimitConfig,
+    ) -> StoreResult<RateLimitState> {
+        let window = self.window(config);
+        let window_ms = window.length.as_millis() as u64;
+        let counter = match self.counters.get(key) {
+            Some(counter) => counter,
+            None => self
+                .counters
+                .entry(key.to_string())
+                .or_insert_with(|| Counter {
+                    state: AtomicU64::new(Counter::pack(window.index, 0)),
+                    window_ms: AtomicU64::new(window_ms),
+                })
+                .downgrade(),
+        };
+        counter.window_ms.store(window_ms, Ordering::Relaxed);
+
+        let max_requests = Self::max_requests(config);
+        let mut current = counter.state.load(Ordering::Acquire);
+        loop {
+            let used = Self::used(current, &window);
+            if used.saturating_add(cost) > max_requests {
+                warn!(
+                    "Rate limit exceeded for key({}): used({}) + cost({}) > max_req({})",
+                    key, used, cost, max_requests
+                );
+                return Ok(Self::state(used, cost, true, &window, config));
+            }
+            let next = Counter::pack(window.index, used.saturating_add(cost) as u64);
+            match counter.state.compare_exchange_weak(
+                current,
+                next,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    return Ok(Self::state(
+                        used.saturating_add(cost),
+                        cost,
+                        false,
+                        &window,
+                        config,
+                    ));
+                }
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    fn backend(&self) -> &'static str {
+        "sharded_memory"
+    }
+
+    fn peek(
+        &self,
+        key: &str,
+        cost: usize,
+        config: &RateLimitConfig,
+    ) -> StoreResult<RateLimitState> {
+        let window = self.window(config);
+        let used = self.counters.get(key).map_or(0, |counter| {
+            Self::used(counter.state.load(Ordering::Acquire), &window)
+        });
+        let limited = used.saturating_add(cost) > Self::max_requests(config);
+        Ok(Self::state(used, cost, limited, &window, config))
+    }
+
+    /// Takes `cost` back from the client's counter if its window has not ended.
+    fn refund(&self, key: &str, cost: usize, config: &RateLimitConfig) -> StoreResult<()> {
+        let window = self.window(config);
+        if let Some(counter) = self.counters.get(key) {
+            let _ = counter
+                .state
+                .fetch_update(
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                    |state| match Counter::unpack(state) {
+                        (index, used) if index == window.index => {
+                            Some(Counter::pack(index, used.saturating_sub(cost as u64)))
+                        }
+                        _ => None,
+                    },
+                );
+        }
+        Ok(())
+    }
+
+    fn reset(&self, key: &str) -> StoreResult<()> {
+        self.counters.remove(key);
+        Ok(())
+    }
+
+    fn ban(&self, key: &str, duration: Option<Duration>) -> StoreResult<()> {
+        let expires = duration.map(|d| Instant::now() + d);
+        self.bans.insert(key.to_string(), expires);
+        Ok(())
+    }
+
+    fn unban(&self, key: &str) -> StoreResult<()> {
+        self.bans.remove(key);
+        Ok(())
+    }
+
+    /// Returns whether the client is banned, dropping its ban once it has expired.
+    fn is_banned(&self, key: &str) -> StoreResult<bool> {
+        let now = Instant::now();
+        self.bans
+            .remove_if(key, |_, expires| expires.is_some_and(|t| t <= now));
+        Ok(self.bans.contains_key(key))
+    }
+
+    fn reset_prefix(&self, prefix: &str) -> StoreResult<usize> {
+        let before = self.counters.len();
+        self.counters.retain(|key, _| !key.starts_with(prefix));
+        Ok(before.saturating_sub(self.counters.len()))
+    }
+
+    fn snapshot(&self, config: &RateLimitConfig) -> StoreResult<Vec<(String, usize)>> {
+        let window = self.window(config);
+        Ok(self
+            .counters
+            .iter()
+            .map(|counter| {
+                let used = Self::used(counter.state.load(Ordering::Acquire), &window);
+                (counter.key().clone(), used)
+            })
+            .filter(|&(_, used)| used > 0)
+            .collect())
+    }
+}
+
+impl ConcurrencyStore for ShardedMemoryStore {
+    /// Increments the client's in-flight counter if it is below `max_concurrent`.
+    fn acquire(&self, key: &str, config: &ConcurrencyConfig) -> StoreResult<Option<u64>> {
+        let mut count = self.in_flight.entry(key.to_string()).or_default();
+        if *count >= config.max_concurrent {
+            return Ok(None);
+        }
+        *count += 1;
+        Ok(Some(0))
+    }
+
+    /// Decrements the client's in-flight counter, removing it once it reaches zero.
+    fn release(&self, key: &str, _lease: u64) -> StoreResult<()> {
+        self.in_flight.remove_if_mut(key, |_, count| {
+            *count = count.saturating_sub(1);
+            *count == 0
+        });
+        Ok(())
+    }
+}
@@ -1,4 +1,53 @@
-use crate::config::RateLimitConfig;
+use std::time::Duration;
+
+use crate::{
+    config::{ConcurrencyConfig, RateLimitConfig},
+    store::{StoreError, StoreFuture, StoreResult},
+};
+
+/// Outcome of a rate limit check for a single client.
+///
+/// All quantities are expressed in cost units, see [`RateLimitConfig::cost`].
+/// The middleware stores the state in the request extensions, so handlers and the
+/// `on_exceed` callback can read it with `req.extensions().get::<RateLimitState>()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitState {
+    /// Whether the request exceeded the limit and was not recorded
+    pub limited: bool,
+    /// Units consumed within the current window, including this request if it was recorded
+    pub used: usize,
+    /// Units still available within the current window
+    pub remaining: usize,
+    /// Time until enough units are freed for the rejected request to fit, zero if allowed
+    pub retry_after: Duration,
+    /// Time until every recorded unit has left the window
+    pub reset_after: Duration,
+}
+
+impl RateLimitState {
+    /// Neutral state used to fold the states of several limits together.
+    pub(crate) const UNLIMITED: RateLimitState = RateLimitState {
+        limited: false,
+        used: 0,
+        remaining: usize::MAX,
+        retry_after: Duration::ZERO,
+        reset_after: Duration::ZERO,
+    };
+
+    /// Returns whichever of the two states restricts the client more.
+    ///
+    /// A limited state wins over an allowed one; between two limited states the longer
+    /// retry-after wins, and between two allowed states the smaller remaining quota wins.
+    pub fn most_restrictive(self, other: RateLimitState) -> RateLimitState {
+        match (self.limited, other.limited) {
+            (true, false) => self,
+            (false, true) => other,
+            (true, true) if other.retry_after > self.retry_after => other,
+            (false, false) if other.remaining < self.remaining => other,
+            _ => self,
+        }
+    }
+}
 
 /// Trait defining the storage interface for rate limiting data.
 ///
@@ -13,45 +62,276 @@ use crate::config::RateLimitConfig;
 ///
 /// # Implementations
 ///
-/// The crate provides two built-in implementations:
+/// The crate provides these built-in implementations:
 /// - [`crate::store::MemoryStore`]: In-memory storage using DashMap
+/// - [`crate::store::ShardedMemoryStore`]: In-memory fixed window counters updated atomically
 /// - [`crate::store::RedisStore`]: Distributed storage using Redis (requires `redis` feature)
+/// - `MemcachedStore`: Distributed storage using memcached (requires `memcached` feature)
+/// - `SqlStore`: Distributed storage using SQLite or PostgreSQL (requires `sqlite` or
+///   `postgres` feature)
 ///
 /// # Custom Implementations
 ///
 /// You can create custom storage backends by implementing this trait:
 ///
 /// ```rust
-/// use actix_web_ratelimit::{store::RateLimitStore, config::RateLimitConfig};
+/// use std::time::Duration;
+/// use actix_web_ratelimit::config::RateLimitConfig;
+/// use actix_web_ratelimit::store::{RateLimitState, RateLimitStore, StoreResult};
 ///
 /// struct CustomStore {
 ///     // Your storage implementation
 /// }
 ///
 /// impl RateLimitStore for CustomStore {
-///     fn is_limited(&self, key: &str, config: &RateLimitConfig) -> bool {
+///     fn check(&self, key: &str, cost: usize, config: &RateLimitConfig) -> StoreResult<RateLimitState> {
 ///         // Your rate limiting logic here
-///         // Return true if client has exceeded the limit
-///         false
+///         Ok(RateLimitState {
+///             limited: false,
+///             used: cost,
+///             remaining: config.max_requests.saturating_sub(cost),
+///             retry_after: Duration::ZERO,
+///             reset_after: config.window_secs,
+///         })
 ///     }
 /// }
 /// ```
 pub trait RateLimitStore: Send + Sync {
     /// Checks if a client has exceeded the rate limit and records the current request.
     ///
+    /// The request is recorded only if `cost` more units fit within the limit, so the
+    /// check and the deduction must happen atomically.
+    ///
     /// # Arguments
     ///
     /// * `key` - Client identifier (typically IP address, but can be customized)
+    /// * `cost` - Number of units this request consumes
     /// * `config` - Rate limiting configuration containing limits and time window
     ///
     /// # Returns
     ///
-    /// * `true` - Client has exceeded the rate limit (request should be rejected)
-    /// * `false` - Client is within limits (request should be allowed)
-    fn is_limited(&self, key: &str, config: &RateLimitConfig) -> bool;
+    /// The [`RateLimitState`] of the client after the check, or a
+    /// [`crate::store::StoreError`] if the backend could not be consulted.
+    fn check(
+        &self,
+        key: &str,
+        cost: usize,
+        config: &RateLimitConfig,
+    ) -> StoreResult<RateLimitState>;
+
+    /// Short name of the backend, used to label logs and traces.
+    fn backend(&self) -> &'static str {
+        "custom"
+    }
+
+    /// Returns the state of a client as if a request of `cost` units were checked,
+    /// without recording it.
+    ///
+    /// Used when requests are only counted after the response is known, see
+    /// [`RateLimitConfig::count_if`]. The default implementation returns
+    /// [`StoreError::Unsupported`].
+    fn peek(
+        &self,
+        _key: &str,
+        _cost: usize,
+        _config: &RateLimitConfig,
+    ) -> StoreResult<RateLimitState> {
+        Err(StoreError::Unsupported("peek"))
+    }
+
+    /// Gives back `cost` units previously recorded by [`RateLimitStore::check`].
+    ///
+    /// Used to refund requests depending on the response, see
+    /// [`RateLimitConfig::refund_if`]. Stores counting fixed windows take the units from
+    /// the current window, so callers should only refund within the `reset_after` of
+    /// the state the check returned, as the middleware does. The default implementation
+    /// returns [`StoreError::Unsupported`].
+    fn refund(&self, _key: &str, _cost: usize, _config: &RateLimitConfig) -> StoreResult<()> {
+        Err(StoreError::Unsupported("refund"))
+    }
+
+    /// Checks several limits at once, recording the request in all of them only if it
+    /// fits within every one.
+    ///
+    /// Used to evaluate a per-client limit together with a [`crate::config::GlobalLimit`].
+    /// The default implementation checks each limit in turn and refunds the earlier
+    /// ones if a later limit rejects the request; backends able to evaluate all keys in
+    /// one atomic operation should override it.
+    ///
+    /// # Arguments
+    ///
+    /// * `checks` - Pairs of store key and the configuration holding its limit and window
+    /// * `cost` - Number of units this request consumes in every limit
+    ///
+    /// # Returns
+    ///
+    /// The [`RateLimitState::most_restrictive`] state among the checked limits.
+    fn check_all(
+        &self,
+        checks: &[(&str, &RateLimitConfig)],
+        cost: usize,
+    ) -> StoreResult<RateLimitState> {
+        check_each(self, checks, cost)
+    }
+
+    /// Like [`RateLimitStore::check_all`], but returns a future the middleware awaits
+    /// instead of blocking its worker.
+    ///
+    /// The default implementation runs [`RateLimitStore::check_all`] right away and
+    /// returns its result. Stores handing checks to another thread, such as
+    /// [`crate::store::BatchingStore`], override it so that a worker can keep serving
+    /// requests while its checks wait.
+    fn check_all_async(
+        &self,
+        checks: &[(&str, &RateLimitConfig)],
+        cost: usize,
+    ) -> StoreFuture<RateLimitState> {
+        Box::pin(std::future::ready(self.check_all(checks, cost)))
+    }
+
+    /// Checks several independent requests, each recorded only if it fits within its own
+    /// limit, e.g. requests of different clients.
+    ///
+    /// Used by [`crate::store::BatchingStore`] to check the requests of concurrent
+    /// workers together. The default implementation calls [`RateLimitStore::check`] for
+    /// each; backends able to send them in one round trip should override it.
+    ///
+    /// # Returns
+    ///
+    /// The result of each check, in the order of `checks`.
+    fn check_batch(
+        &self,
+        checks: &[(&str, usize, &RateLimitConfig)],
+    ) -> Vec<StoreResult<RateLimitState>> {
+        checks
+            .iter()
+            .map(|&(key, cost, config)| self.check(key, cost, config))
+            .collect()
+    }
+
+    /// Peeks at several independent requests, without recording any of them.
+    ///
+    /// Used by [`crate::store::HybridStore`] to read back the usage of all its clients
+    /// at once. The default implementation calls [`RateLimitStore::peek`] for each;
+    /// backends able to send them in one round trip should override it.
+    ///
+    /// # Returns
+    ///
+    /// The result of each peek, in the order of `checks`.
+    fn peek_batch(
+        &self,
+        checks: &[(&str, usize, &RateLimitConfig)],
+    ) -> Vec<StoreResult<RateLimitState>> {
+        checks
+            .iter()
+            .map(|&(key, cost, config)| self.peek(key, cost, config))
+            .collect()
+    }
+
+    /// Clears the requests recorded for a client, giving it its full quota back.
+    ///
+    /// Bans are left untouched. The default implementation returns
+    /// [`StoreError::Unsupported`].
+    fn reset(&self, _key: &str) -> StoreResult<()> {
+        Err(StoreError::Unsupported("reset"))
+    }
+
+    /// Bans a client, rejecting all its requests until `duration` elapses, or until
+    /// [`RateLimitStore::unban`] is called if `duration` is `None`.
+    ///
+    /// The default implementation returns [`StoreError::Unsupported`].
+    fn ban(&self, _key: &str, _duration: Option<Duration>) -> StoreResult<()> {
+        Err(StoreError::Unsupported("ban"))
+    }
+
+    /// Lifts the ban of a client. The default implementation returns
+    /// [`StoreError::Unsupported`].
+    fn unban(&self, _key: &str) -> StoreResult<()> {
+        Err(StoreError::Unsupported("unban"))
+    }
+
+    /// Returns whether a client is currently banned.
+    ///
+    /// Called by the middleware before every check if
+    /// [`RateLimitConfig::enforce_bans`] is set. The default implementation returns
+    /// `false`, so stores without ban support never reject anyone.
+    fn is_banned(&self, _key: &str) -> StoreResult<bool> {
+        Ok(false)
+    }
+
+    /// Returns whether each of several clients is currently banned.
+    ///
+    /// Used by [`crate::store::HybridStore`] to refresh the ban status of all its
+    /// clients at once. The default implementation calls [`RateLimitStore::is_banned`]
+    /// for each; backends able to send them in one round trip should override it.
+    fn is_banned_batch(&self, keys: &[&str]) -> Vec<StoreResult<bool>> {
+        keys.iter().map(|key| self.is_banned(key)).collect()
+    }
+
+    /// Clears the requests recorded for every client whose identifier starts with `prefix`.
+    ///
+    /// Bans are left untouched. The default implementation returns
+    /// [`StoreError::Unsupported`].
+    ///
+    /// # Returns
+    ///
+    /// The number of clients that were reset.
+    fn reset_prefix(&self, _prefix: &str) -> StoreResult<usize> {
+        Err(StoreError::Unsupported("reset_prefix"))
+    }
+
+    /// Clears the requests recorded for all clients, see [`RateLimitStore::reset_prefix`].
+    fn reset_all(&self) -> StoreResult<usize> {
+        self.reset_prefix("")
+    }
+
+    /// Lists every client with units used within the window, along with that usage.
+    ///
+    /// The list is a point-in-time copy in no particular order; clients may be added or
+    /// expire while it is being built. The default implementation returns
+    /// [`StoreError::Unsupported`].
+    fn snapshot(&self, _config: &RateLimitConfig) -> StoreResult<Vec<(String, usize)>> {
+        Err(StoreError::Unsupported("snapshot"))
+    }
+
+    /// Lists up to `limit` clients using the most units within the window, heaviest first.
+    ///
+    /// The default implementation sorts the [`RateLimitStore::snapshot`].
+    fn top_keys(
+        &self,
+        limit: usize,
+        config: &RateLimitConfig,
+    ) -> StoreResult<Vec<(String, usize)>> {
+        let mut usage = self.snapshot(config)?;
+        usage.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        usage.truncate(limit);
+        Ok(usage)
+    }
+}
+
+/// Checks each limit in turn, refunding the earlier ones if a later limit rejects the
+/// request. Default implementation of [`RateLimitStore::check_all`].
+pub(crate) fn check_each<S: RateLimitStore + ?Sized>(
+    store: &S,
+    checks: &[(&str, &RateLimitConfig)],
+    cost: usize,
+) -> StoreResult<RateLimitState> {
+    let mut result = RateLimitState::UNLIMITED;
+    for (i, &(key, config)) in checks.iter().enumerate() {
+        let state = store.check(key, cost, config)?;
+        if state.limited {
+            // Give back what the earlier limits recorded
+            for &(key, config) in &checks[..i] {
+                store.refund(key, cost, config)?;
+            }
+            return Ok(state);
+        }
+        result = result.most_restrictive(state);
+    }
+    Ok(result)
 }
 
-/// Implementation of [`RateLimitStore`] for `Box<dyn RateLimitStore>` to support dynamic dispatch.
+/// Implementation of [`RateLimitStore`] for `Box<T>` to support dynamic dispatch.
 ///
 /// This allows using different store implementations behind a trait object,
 /// enabling runtime selection of storage backends.
@@ -64,14 +344,106 @@ pub trait RateLimitStore: Send + Sync {
 /// let store: Box<dyn RateLimitStore> = Box::new(MemoryStore::new());
 /// // Now you can use `store` as a trait object
 /// ```
-impl RateLimitStore for Box<dyn RateLimitStore> {
+impl<T: RateLimitStore + ?Sized> RateLimitStore for Box<T> {
     /// Delegates to the underlying implementation.
-    fn is_limited(&self, key: &str, config: &RateLimitConfig) -> bool {
-        (**self).is_limited(key, config)
+    fn check(
+        &self,
+        key: &str,
+        cost: usize,
+        config: &RateLimitConfig,
+    ) -> StoreResult<RateLimitState> {
+        (**self).check(key, cost, config)
+    }
+
+    fn peek(
+        &self,
+        key: &str,
+        cost: usize,
+        config: &RateLimitConfig,
+    ) -> StoreResult<RateLimitState> {
+        (**self).peek(key, cost, config)
+    }
+
+    fn refund(&self, key: &str, cost: usize, config: &RateLimitConfig) -> StoreResult<()> {
+        (**self).refund(key, cost, config)
+    }
+
+    fn check_all(
+        &self,
+        checks: &[(&str, &RateLimitConfig)],
+        cost: usize,
+    ) -> StoreResult<RateLimitState> {
+        (**self).check_all(checks, cost)
+    }
+
+    fn check_all_async(
+        &self,
+        checks: &[(&str, &RateLimitConfig)],
+        cost: usize,
+    ) -> StoreFuture<RateLimitState> {
+        (**self).check_all_async(checks, cost)
+    }
+
+    fn check_batch(
+        &self,
+        checks: &[(&str, usize, &RateLimitConfig)],
+    ) -> Vec<StoreResult<RateLimitState>> {
+        (**self).check_batch(checks)
+    }
+
+    fn peek_batch(
+        &self,
+        checks: &[(&str, usize, &RateLimitConfig)],
+    ) -> Vec<StoreResult<RateLimitState>> {
+        (**self).peek_batch(checks)
+    }
+
+    fn backend(&self) -> &'static str {
+        (**self).backend()
+    }
+
+    fn reset(&self, key: &str) -> StoreResult<()> {
+        (**self).reset(key)
+    }
+
+    fn ban(&self, key: &str, duration: Option<Duration>) -> StoreResult<()> {
+        (**self).ban(key, duration)
+    }
+
+    fn unban(&self, key: &str) -> StoreResult<()> {
+        (**self).unban(key)
+    }
+
+    fn is_banned(&self, key: &str) -> StoreResult<bool> {
+        (**self).is_banned(key)
+    }
+
+    fn is_banned_batch(&self, keys: &[&str]) -> Vec<StoreResult<bool>> {
+        (**self).is_banned_batch(keys)
+    }
+
+    fn reset_prefix(&self, prefix: &str) -> StoreResult<usize> {
+        (**self).reset_prefix(prefix)
+    }
+
+    fn reset_all(&self) -> StoreResult<usize> {
+        (**self).reset_all()
+    }
+
+    fn snapshot(&self, config: &RateLimitConfig) -> StoreResult<Vec<(String, usize)>> {
+        (**self).snapshot(config)
+    }
+
+    fn top_keys(
+        &self,
+        limit: usize,
+        config: &RateLimitConfig,
+    ) -> StoreResult<Vec<(String, usize)>> {
+        (**self).top_keys(limit, config)
     }
 }
 
-/// Implementation of [`RateLimitStore`] for `Arc<dyn RateLimitStore>` to support shared ownership.
+/// Implementation of [`RateLimitStore`] for `Arc<T>` to support shared ownership.
 ///
 /// This allows sharing the same store implementation across multiple threads
 /// and middleware instances using atomic reference counting.
@@ -86,9 +458,157 @@ impl RateLimitStore for Box<dyn RateLimitStore> {
 /// let store_clone = store.clone();
 /// // Both `store` and `store_clone` reference the same underlying implementation
 /// ```
-impl RateLimitStore for std::sync::Arc<dyn RateLimitStore> {
+impl<T: RateLimitStore + ?Sized> RateLimitStore for std::sync::Arc<T> {
     /// Delegates to the underlying implementation.
-    fn is_limited(&self, key: &str, config: &RateLimitConfig) -> bool {
-        (**self).is_limited(key, config)
+    fn check(
+        &self,
+        key: &str,
+        cost: usize,
+        config: &RateLimitConfig,
+    ) -> StoreResult<RateLimitState> {
+        (**self).check(key, cost, config)
+    }
+
+    fn peek(
+        &self,
+        key: &str,
+        cost: usize,
+        config: &RateLimitConfig,
+    ) -> StoreResult<RateLimitState> {
+        (**self).peek(key, cost, config)
+    }
+
+    fn refund(&self, key: &str, cost: usize, config: &RateLimitConfig) -> StoreResult<()> {
+        (**self).refund(key, cost, config)
+    }
+
+    fn check_all(
+        &self,
+        checks: &[(&str, &RateLimitConfig)],
+        cost: usize,
+    ) -> StoreResult<RateLimitState> {
+        (**self).check_all(checks, cost)
+    }
+
+    fn check_all_async(
+        &self,
+        checks: &[(&str, &RateLimitConfig)],
+        cost: usize,
+    ) -> StoreFuture<RateLimitState> {
+        (**self).check_all_async(checks, cost)
+    }
+
+    fn check_batch(
+        &self,
+        checks: &[(&str, usize, &RateLimitConfig)],
+    ) -> Vec<StoreResult<RateLimitState>> {
+        (**self).check_batch(checks)
+    }
+
+    fn peek_batch(
+        &self,
+        checks: &[(&str, usize, &RateLimitConfig)],
+    ) -> Vec<StoreResult<RateLimitState>> {
+        (**self).peek_batch(checks)
+    }
+
+    fn backend(&self) -> &'static str {
+        (**self).backend()
+    }
+
+    fn reset(&self, key: &str) -> StoreResult<()> {
+        (**self).reset(key)
+    }
+
+    fn ban(&self, key: &str, duration: Option<Duration>) -> StoreResult<()> {
+        (**self).ban(key, duration)
+    }
+
+    fn unban(&self, key: &str) -> StoreResult<()> {
+        (**self).unban(key)
+    }
+
+    fn is_banned(&self, key: &str) -> StoreResult<bool> {
+        (**self).is_banned(key)
+    }
+
+    fn is_banned_batch(&self, keys: &[&str]) -> Vec<StoreResult<bool>> {
+        (**self).is_banned_batch(keys)
+    }
+
+    fn reset_prefix(&self, prefix: &str) -> StoreResult<usize> {
+        (**self).reset_prefix(prefix)
+    }
+
+    fn reset_all(&self) -> StoreResult<usize> {
+        (**self).reset_all()
+    }
+
+    fn snapshot(&self, config: &RateLimitConfig) -> StoreResult<Vec<(String, usize)>> {
+        (**self).snapshot(config)
+    }
+
+    fn top_keys(
+        &self,
+        limit: usize,
+        config: &RateLimitConfig,
+    ) -> StoreResult<Vec<(String, usize)>> {
+        (**self).top_keys(limit, config)
+    }
+}
+
+/// Trait defining the storage interface for in-flight request permits.
+///
+/// Used by [`crate::ConcurrencyLimit`] to cap the number of requests a client may have
+/// in flight. A permit is acquired before the inner service runs and released when the
+/// response future completes or is dropped.
+///
+/// Both built-in stores implement this trait, so the same store instance can back a
+/// [`crate::RateLimit`] and a [`crate::ConcurrencyLimit`] middleware.
+pub trait ConcurrencyStore: Send + Sync {
+    /// Tries to take one of the client's `max_concurrent` permits.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(lease)` - The permit was granted, `lease` identifies it for [`ConcurrencyStore::release`]
+    /// * `None` - The client already has `max_concurrent` requests in flight
+    fn acquire(&self, key: &str, config: &ConcurrencyConfig) -> StoreResult<Option<u64>>;
+
+    /// Gives back a permit previously granted by [`ConcurrencyStore::acquire`].
+    fn release(&self, key: &str, lease: u64) -> StoreResult<()>;
+
+    /// Whether the store is reached over the network, in which case
+    /// [`crate::ConcurrencyLimit`] releases permits from a background thread instead of
+    /// the worker. Defaults to `false`.
+    fn remote(&self) -> bool {
+        false
+    }
+}
+
+impl<T: ConcurrencyStore + ?Sized> ConcurrencyStore for Box<T> {
+    fn acquire(&self, key: &str, config: &ConcurrencyConfig) -> StoreResult<Option<u64>> {
+        (**self).acquire(key, config)
+    }
+
+    fn release(&self, key: &str, lease: u64) -> StoreResult<()> {
+        (**self).release(key, lease)
+    }
+
+    fn remote(&self) -> bool {
+        (**self).remote()
+    }
+}
+
+impl<T: ConcurrencyStore + ?Sized> ConcurrencyStore for std::sync::Arc<T> {
+    fn acquire(&self, key: &str, config: &ConcurrencyConfig) -> StoreResult<Option<u64>> {
+        (**self).acquire(key, config)
+    }
+
+    fn release(&self, key: &str, lease: u64) -> StoreResult<()> {
+        (**self).release(key, lease)
+    }
+
+    fn remote(&self) -> bool {
+        (**self).remote()
     }
 }
@@ -0,0 +1,259 @@
+//! Instrumentation of the rate limiting middleware.
+//!
+//! With the `metrics` feature enabled, decisions and store latency are reported through
+//! the [`metrics`](https://docs.rs/metrics) facade; install any recorder, e.g.
+//! `metrics-exporter-prometheus`, to expose them:
+//!
+//! - `ratelimit_decisions_total` (counter): labelled by `policy`, `route` and
+//!   `decision`, one of `allowed`, `limited`, `would_limit` (shadow mode), `banned`
+//!   or `error`
+//! - `ratelimit_store_duration_seconds` (histogram): latency of store calls,
+//!   labelled by `policy` and `route`
+//!
+//! `route` is the pattern of the resource the request matched, e.g. `/users/{id}`, or
+//! `unmatched`, so its values are bounded by the routes registered on the app.
+//!
+//! With the `tracing` feature enabled, every request gets a `ratelimit` span carrying
+//! `policy`, `key`, `backend`, `decision` and `remaining`, and every store call runs in a
+//! `ratelimit.store` span carrying `policy`, `backend` and `op`.
+//!
+//! Without the features every function here is a no-op.
+
+use actix_web::dev::ServiceRequest;
+use std::future::Future;
+
+use crate::{
+    config::RateLimitConfig,
+    store::{RateLimitState, StoreResult},
+};
+
+/// Outcome of the check made before the inner service runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Decision {
+    Allowed,
+    Limited,
+    /// Limited in shadow mode, the request was let through
+    WouldLimit,
+    /// Rejected because the client is banned, the limit was not checked
+    Banned,
+    Error,
+}
+
+impl Decision {
+    pub(crate) fn of(result: &StoreResult<RateLimitState>, shadow: bool) -> Self {
+        match result {
+            Ok(state) if state.limited && shadow => Decision::WouldLimit,
+            Ok(state) if state.limited => Decision::Limited,
+            Ok(_) => Decision::Allowed,
+            Err(_) => Decision::Error,
+        }
+    }
+
+    #[cfg(any(feature = "metrics", feature = "tracing"))]
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Decision::Allowed => "allowed",
+            Decision::Limited => "limited",
+            Decision::WouldLimit => "would_limit",
+            Decision::Banned => "banned",
+            Decision::Error => "error",
+        }
+    }
+}
+
+/// Returns the `route` label of a request, empty without the `metrics` feature.
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn route_label(req: &ServiceRequest) -> String {
+    #[cfg(feature = "metrics")]
+    return req
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+    #[cfg(not(feature = "metrics"))]
+    String::new()
+}
+
+/// Records the decision taken for a request to `route`.
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn record_decision(config: &RateLimitConfig, route: &str, decision: Decision) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!(
+        "ratelimit_decisions_total",
+        "policy" => config.policy.clone(),
+        "route" => route.to_string(),
+        "decision" => decision.as_str(),
+    )
+    .increment(1);
+}
+
+/// Runs a store call made for a request to `route`, recording how long it took.
+#[cfg_attr(
+    not(all(feature = "tracing", feature = "metrics")),
+    allow(unused_variables)
+)]
+pub(crate) fn timed<T>(
+    config: &RateLimitConfig,
+    route: &str,
+    backend: &'static str,
+    op: &'static str,
+    call: impl FnOnce() -> T,
+) -> T {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!(
+        "ratelimit.store",
+        policy = %config.policy,
+        backend,
+        op,
+    )
+    .entered();
+
+    #[cfg(feature = "metrics")]
+    let started = std::time::Instant::now();
+    let result = call();
+    #[cfg(feature = "metrics")]
+    metrics::histogram!(
+        "ratelimit_store_duration_seconds",
+        "policy" => config.policy.clone(),
+        "route" => route.to_string(),
+    )
+    .record(started.elapsed().as_secs_f64());
+    result
+}
+
+/// Awaits a store call made for a request to `route`, recording how long it took until
+/// it resolved.
+#[cfg_attr(
+    not(all(feature = "tracing", feature = "metrics")),
+    allow(unused_variables)
+)]
+pub(crate) async fn timed_async<F: Future>(
+    config: &RateLimitConfig,
+    route: &str,
+    backend: &'static str,
+    op: &'static str,
+    call: F,
+) -> F::Output {
+    #[cfg(feature = "tracing")]
+    let call = tracing::Instrument::instrument(
+        call,
+        tracing::debug_span!(
+            "ratelimit.store",
+            policy = %config.policy,
+            backend,
+            op,
+        ),
+    );
+
+    #[cfg(feature = "metrics")]
+    let started = std::time::Instant::now();
+    let result = call.await;
+    #[cfg(feature = "metrics")]
+    metrics::histogram!(
+        "ratelimit_store_duration_seconds",
+        "policy" => config.policy.clone(),
+        "route" => route.to_string(),
+    )
+    .record(started.elapsed().as_secs_f64());
+    result
+}
+
+/// Span covering the rate limit check and handling of one request.
+#[derive(Clone)]
+pub(crate) struct CheckSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl CheckSpan {
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) fn new(config: &RateLimitConfig, key: &str, backend: &'static str) -> Self {
+        Self {
+            #[cfg(feature = "tracing")]
+            span: tracing::info_span!(
+                "ratelimit",
+                policy = %config.policy,
+                key = %display_key(config, key),
+                backend,
+                decision = tracing::field::Empty,
+                remaining = tracing::field::Empty,
+            ),
+        }
+    }
+
+    /// Attaches the decision to the span and emits a matching event.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) fn record(&self, decision: Decision, result: &StoreResult<RateLimitState>) {
+        #[cfg(feature = "tracing")]
+        {
+            let shadow = decision == Decision::WouldLimit;
+            let decision = decision.as_str();
+            self.span.record("decision", decision);
+            match result {
+                Ok(state) => {
+                    self.span.record("remaining", state.remaining);
+                    if shadow {
+                        tracing::info!(
+                            parent: &self.span,
+                            decision,
+                            remaining = state.remaining,
+                            "rate limit would have been exceeded"
+                        );
+                    } else if state.limited {
+                        tracing::info!(
+                            parent: &self.span,
+                            decision,
+                            remaining = state.remaining,
+                            retry_after_ms = state.retry_after.as_millis() as u64,
+                            "rate limit exceeded"
+                        );
+                    } else {
+                        tracing::debug!(
+                            parent: &self.span,
+                            decision,
+                            remaining = state.remaining,
+                            "rate limit check passed"
+                        );
+                    }
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        parent: &self.span,
+                        decision,
+                        error = %err,
+                        "rate limit store error"
+                    );
+                }
+            }
+        }
+    }
+
+    /// Attaches the banned decision to the span and emits a matching event.
+    pub(crate) fn record_banned(&self) {
+        #[cfg(feature = "tracing")]
+        {
+            let decision = Decision::Banned.as_str();
+            self.span.record("decision", decision);
+            tracing::info!(parent: &self.span, decision, "client is banned");
+        }
+    }
+
+    /// Runs the future within the span.
+    pub(crate) fn instrument<F: Future>(self, fut: F) -> impl Future<Output = F::Output> {
+        #[cfg(feature = "tracing")]
+        return tracing::Instrument::instrument(fut, self.span);
+        #[cfg(not(feature = "tracing"))]
+        fut
+    }
+}
+
+/// Formats the client identifier for traces, hashing it if configured.
+#[cfg(feature = "tracing")]
+fn display_key(config: &RateLimitConfig, key: &str) -> String {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    if !config.hash_keys {
+        return key.to_string();
+    }
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
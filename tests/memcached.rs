@@ -0,0 +1,221 @@
+//! Spawns `memcached` on a free port when it is on the `PATH`, or uses the server at
+//! `MEMCACHED_URL`; the tests are skipped when neither is available:
+//!
+//! ```sh
+//! MEMCACHED_URL=127.0.0.1:11211 cargo test --features memcached --test memcached
+//! ```
+#![cfg(feature = "memcached")]
+
+use actix_web_ratelimit::config::RateLimitConfig;
+use actix_web_ratelimit::store::{MemcachedAlgorithm, MemcachedStore, RateLimitStore};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A memcached server for one test, stopped when dropped if it was spawned.
+struct Server {
+    url: String,
+    child: Option<Child>,
+}
+
+impl Server {
+    fn start() -> Option<Server> {
+        if let Ok(url) = std::env::var("MEMCACHED_URL") {
+            return Some(Server { url, child: None });
+        }
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("Failed to find a free port")
+            .port();
+        let child = match Command::new("memcached")
+            .args(["-l", "127.0.0.1", "-p", &port.to_string(), "-U", "0"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(_) => {
+                eprintln!("memcached is not on the PATH and MEMCACHED_URL is unset, skipping");
+                return None;
+            }
+        };
+        let server = Server {
+            url: format!("127.0.0.1:{}", port),
+            child: Some(child),
+        };
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while TcpStream::connect(&server.url).is_err() {
+            assert!(Instant::now() < deadline, "memcached did not start");
+            thread::sleep(Duration::from_millis(20));
+        }
+        Some(server)
+    }
+
+    fn store(&self, prefix: &str) -> MemcachedStore {
+        MemcachedStore::new(&[self.url.as_str()])
+            .expect("Failed to connect to memcached")
+            .with_prefix(prefix)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+#[test]
+fn fixed_window_limits_clients() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+    let store = server.store("test:fixed:");
+    let config = RateLimitConfig::default().max_requests(3).window_secs(10);
+    store.reset("client").unwrap();
+
+    for remaining in [2, 1, 0] {
+        assert_eq!(
+            store.check("client", 1, &config).unwrap().remaining,
+            remaining
+        );
+    }
+    assert!(store.check("client", 1, &config).unwrap().limited);
+}
+
+#[test]
+fn token_bucket_limits_clients() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+    let store = server
+        .store("test:bucket:")
+        .with_algorithm(MemcachedAlgorithm::TokenBucket);
+    let config = RateLimitConfig::default().max_requests(2).window_secs(10);
+    store.reset("client").unwrap();
+
+    assert!(!store.check("client", 2, &config).unwrap().limited);
+    assert!(store.check("client", 1, &config).unwrap().limited);
+}
+
+#[test]
+fn windows_longer_than_30_days_are_kept() {
+    let config = RateLimitConfig::default().max_requests(1).window(DAY * 40);
+    let Some(server) = Server::start() else {
+        return;
+    };
+    for algorithm in [
+        MemcachedAlgorithm::FixedWindow,
+        MemcachedAlgorithm::TokenBucket,
+    ] {
+        let store = server.store("test:long:").with_algorithm(algorithm);
+        store.reset("client").unwrap();
+
+        assert!(!store.check("client", 1, &config).unwrap().limited);
+        assert!(store.check("client", 1, &config).unwrap().limited);
+        store.reset("client").unwrap();
+    }
+}
+
+#[test]
+fn bans_longer_than_30_days_are_kept() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+    let store = server.store("test:ban:");
+    store.ban("client", Some(DAY * 40)).unwrap();
+    assert!(store.is_banned("client").unwrap());
+    store.unban("client").unwrap();
+    assert!(!store.is_banned("client").unwrap());
+}
+
+#[test]
+fn invalid_keys_are_hashed() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+    let store = server.store("test:hashed:");
+    let config = RateLimitConfig::default().max_requests(1).window_secs(10);
+    let key = format!("client with spaces {}", "x".repeat(300));
+    store.reset(&key).unwrap();
+
+    assert!(!store.check(&key, 1, &config).unwrap().limited);
+    assert!(store.check(&key, 1, &config).unwrap().limited);
+}
+
+#[test]
+fn sub_second_windows_start_over() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+    let store = server.store("test:subsecond:");
+    let config = RateLimitConfig::default()
+        .max_requests(1)
+        .window(Duration::from_millis(200));
+    store.reset("client").unwrap();
+
+    assert!(!store.check("client", 1, &config).unwrap().limited);
+    let state = store.check("client", 1, &config).unwrap();
+    assert!(state.limited);
+    std::thread::sleep(state.retry_after);
+    assert!(!store.check("client", 1, &config).unwrap().limited);
+}
+
+#[test]
+fn concurrent_first_requests_share_the_window() {
+    // Every thread finds the counter missing, so all but one lose the `add` race
+    let Some(server) = Server::start() else {
+        return;
+    };
+    let store = Arc::new(server.store("test:race:"));
+    let config = RateLimitConfig::default().max_requests(5).window_secs(10);
+    let threads = 16;
+    for _ in 0..20 {
+        store.reset("client").unwrap();
+        let barrier = Arc::new(Barrier::new(threads));
+        let allowed = (0..threads)
+            .map(|_| {
+                let (store, barrier, config) = (store.clone(), barrier.clone(), config.clone());
+                thread::spawn(move || {
+                    barrier.wait();
+                    !store.check("client", 1, &config).unwrap().limited
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .filter(|allowed| *allowed)
+            .count();
+
+        // Racing over-limit increments may turn away a few more, but none are lost
+        assert!((1..=5).contains(&allowed));
+        assert_eq!(store.peek("client", 0, &config).unwrap().used, allowed);
+    }
+}
+
+#[test]
+fn limited_requests_are_taken_back() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+    let store = server.store("test:decr:");
+    let config = RateLimitConfig::default().max_requests(3).window_secs(10);
+    store.reset("client").unwrap();
+
+    store.check("client", 2, &config).unwrap();
+    for _ in 0..5 {
+        let state = store.check("client", 2, &config).unwrap();
+        assert!(state.limited);
+        assert_eq!(state.used, 2);
+    }
+    // Nothing of the limited requests is left on the counter
+    assert_eq!(store.peek("client", 0, &config).unwrap().used, 2);
+    assert!(!store.check("client", 1, &config).unwrap().limited);
+    assert!(store.check("client", 1, &config).unwrap().limited);
+}
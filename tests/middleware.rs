@@ -0,0 +1,481 @@
+//! Exercises the middleware on the request path of a test service.
+
+use actix_web::{
+    App, HttpResponse,
+    http::{StatusCode, header::HeaderName},
+    test, web,
+};
+use actix_web_ratelimit::{
+    ConcurrencyLimit, ConfigHandle, RateLimit,
+    config::{ConcurrencyConfig, RateLimitConfig},
+    store::{MemoryStore, RateLimitState, RateLimitStore, StoreError, StoreResult},
+};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// Memory store counting the refunds it gets.
+#[derive(Default)]
+struct Refunds {
+    store: MemoryStore,
+    refunds: AtomicUsize,
+}
+
+impl RateLimitStore for Refunds {
+    fn check(
+        &self,
+        key: &str,
+        cost: usize,
+        config: &RateLimitConfig,
+    ) -> StoreResult<RateLimitState> {
+        self.store.check(key, cost, config)
+    }
+
+    fn refund(&self, key: &str, cost: usize, config: &RateLimitConfig) -> StoreResult<()> {
+        self.refunds.fetch_add(1, Ordering::SeqCst);
+        self.store.refund(key, cost, config)
+    }
+}
+
+/// Store whose backend is unreachable.
+struct Unreachable;
+
+impl RateLimitStore for Unreachable {
+    fn check(&self, _: &str, _: usize, _: &RateLimitConfig) -> StoreResult<RateLimitState> {
+        Err(StoreError::Other("connection refused".to_string()))
+    }
+}
+
+/// Request to `path` from the client `10.0.0.1`.
+fn request(path: &str) -> test::TestRequest {
+    request_from(path, "10.0.0.1")
+}
+
+/// Request to `path` from the client `ip`.
+fn request_from(path: &str, ip: &str) -> test::TestRequest {
+    test::TestRequest::get()
+        .uri(path)
+        .peer_addr(format!("{}:1234", ip).parse().unwrap())
+}
+
+#[actix_web::test]
+async fn allows_exactly_max_requests() {
+    let config = RateLimitConfig::default().max_requests(3).window_secs(60);
+    let app = test::init_service(
+        App::new()
+            .wrap(RateLimit::new(config, MemoryStore::new()))
+            .route("/", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+
+    for _ in 0..3 {
+        let res = test::call_service(&app, request("/").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+    let res = test::call_service(&app, request("/").to_request()).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_web::test]
+async fn counts_only_matching_statuses() {
+    let config = RateLimitConfig::default()
+        .max_requests(2)
+        .count_if(|status| status == StatusCode::UNAUTHORIZED);
+    let app = test::init_service(
+        App::new()
+            .wrap(RateLimit::new(config, MemoryStore::new()))
+            .route("/", web::get().to(HttpResponse::Ok))
+            .route("/login", web::get().to(HttpResponse::Unauthorized)),
+    )
+    .await;
+
+    for _ in 0..5 {
+        let res = test::call_service(&app, request("/").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+    for _ in 0..2 {
+        let res = test::call_service(&app, request("/login").to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+    let res = test::call_service(&app, request("/").to_request()).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_web::test]
+async fn refunds_matching_statuses() {
+    let store = Arc::new(Refunds::default());
+    let config = RateLimitConfig::default()
+        .max_requests(1)
+        .refund_if(|status| status.is_server_error());
+    let app = test::init_service(
+        App::new()
+            .wrap(RateLimit::new(config, store.clone()))
+            .route("/", web::get().to(HttpResponse::Ok))
+            .route("/fail", web::get().to(HttpResponse::BadGateway)),
+    )
+    .await;
+
+    for _ in 0..3 {
+        let res = test::call_service(&app, request("/fail").to_request()).await;
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    }
+    assert_eq!(store.refunds.load(Ordering::SeqCst), 3);
+    let res = test::call_service(&app, request("/").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = test::call_service(&app, request("/").to_request()).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(store.refunds.load(Ordering::SeqCst), 3);
+}
+
+#[actix_web::test]
+async fn refunds_only_within_the_window_of_the_check() {
+    let store = Arc::new(Refunds::default());
+    let config = RateLimitConfig::default()
+        .max_requests(10)
+        .window(Duration::from_millis(50))
+        .refund_if(|status| status.is_server_error());
+    let app = test::init_service(
+        App::new()
+            .wrap(RateLimit::new(config, store.clone()))
+            .route(
+                "/",
+                web::get().to(|| async { HttpResponse::InternalServerError().finish() }),
+            )
+            .route(
+                "/slow",
+                web::get().to(|| async {
+                    actix_web::rt::time::sleep(Duration::from_millis(100)).await;
+                    HttpResponse::InternalServerError().finish()
+                }),
+            ),
+    )
+    .await;
+
+    let res = test::call_service(&app, request("/").to_request()).await;
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(store.refunds.load(Ordering::SeqCst), 1);
+
+    // The window of the check has ended by the time the response is known
+    test::call_service(&app, request("/slow").to_request()).await;
+    assert_eq!(store.refunds.load(Ordering::SeqCst), 1);
+}
+
+#[actix_web::test]
+async fn delay_queues_are_per_middleware() {
+    let config = RateLimitConfig::default()
+        .max_requests(1)
+        .window(Duration::from_millis(200))
+        .delay(Duration::from_secs(1), 1);
+    let first = test::init_service(
+        App::new()
+            .wrap(RateLimit::new(config.clone(), MemoryStore::new()))
+            .route("/", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+    let second = test::init_service(
+        App::new()
+            .wrap(RateLimit::new(config, MemoryStore::new()))
+            .route("/", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+    test::call_service(&first, request("/").to_request()).await;
+    test::call_service(&second, request("/").to_request()).await;
+
+    // Same policy and client, but each middleware has a queue of its own
+    let (queued, rejected, other) = futures_util::join!(
+        test::call_service(&first, request("/").to_request()),
+        test::call_service(&first, request("/").to_request()),
+        test::call_service(&second, request("/").to_request()),
+    );
+    assert_eq!(queued.status(), StatusCode::OK);
+    assert_eq!(rejected.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(other.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn permits_are_held_until_the_body_is_sent() {
+    let config = ConcurrencyConfig::default().max_concurrent(1);
+    let app = test::init_service(
+        App::new()
+            .wrap(ConcurrencyLimit::new(config, MemoryStore::new()))
+            .route(
+                "/",
+                web::get().to(|| async { HttpResponse::Ok().body("report") }),
+            ),
+    )
+    .await;
+
+    let streaming = test::call_service(&app, request("/").to_request()).await;
+    assert_eq!(streaming.status(), StatusCode::OK);
+    let res = test::call_service(&app, request("/").to_request()).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    assert_eq!(test::read_body(streaming).await, "report");
+    let res = test::call_service(&app, request("/").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    drop(res);
+
+    // Dropping a response unsent releases its permit as well
+    let res = test::call_service(&app, request("/").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn global_limit_is_shared_by_all_clients() {
+    let config =
+        RateLimitConfig::default()
+            .max_requests(2)
+            .global_limit("all", 3, Duration::from_secs(60));
+    let app = test::init_service(
+        App::new()
+            .wrap(RateLimit::new(config, MemoryStore::new()))
+            .route("/", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+
+    for ip in ["10.0.0.1", "10.0.0.1", "10.0.0.2"] {
+        let res = test::call_service(&app, request_from("/", ip).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+    // Within its own limit, but over the global one
+    let res = test::call_service(&app, request_from("/", "10.0.0.3").to_request()).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_web::test]
+async fn shadow_mode_lets_limited_requests_through() {
+    let store = Arc::new(MemoryStore::new());
+    let config = RateLimitConfig::default()
+        .max_requests(1)
+        .enforce_bans(true)
+        .shadow(Some(HeaderName::from_static("x-ratelimit-shadow")));
+    let app = test::init_service(
+        App::new()
+            .wrap(RateLimit::new(config.clone(), store.clone()))
+            .route("/", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+
+    let res = test::call_service(&app, request("/").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers().get("x-ratelimit-shadow").is_none());
+    store.ban("10.0.0.1", None).unwrap();
+    for _ in 0..2 {
+        let res = test::call_service(&app, request("/").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get("x-ratelimit-shadow").unwrap(),
+            "would-limit"
+        );
+    }
+    // Still recorded as usual
+    assert_eq!(store.peek("10.0.0.1", 0, &config).unwrap().used, 1);
+    assert!(store.peek("10.0.0.1", 1, &config).unwrap().limited);
+}
+
+#[actix_web::test]
+async fn routes_have_budgets_of_their_own() {
+    let config = RateLimitConfig::default().max_requests(1).route_limit(
+        "/export",
+        2,
+        Duration::from_secs(60),
+    );
+    let app = test::init_service(
+        App::new()
+            .wrap(RateLimit::new(config, MemoryStore::new()))
+            .default_service(web::to(HttpResponse::Ok)),
+    )
+    .await;
+
+    for path in ["/export", "/export/csv"] {
+        let res = test::call_service(&app, request(path).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+    let res = test::call_service(&app, request("/export/pdf").to_request()).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // Not under the route prefix, counted against the default limit
+    let res = test::call_service(&app, request("/exports").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = test::call_service(&app, request("/").to_request()).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_web::test]
+async fn updates_apply_to_running_middleware() {
+    let handle = ConfigHandle::new(RateLimitConfig::default().max_requests(1));
+    let app = test::init_service(
+        App::new()
+            .wrap(RateLimit::with_handle(handle.clone(), MemoryStore::new()))
+            .default_service(web::to(HttpResponse::Ok)),
+    )
+    .await;
+
+    test::call_service(&app, request("/").to_request()).await;
+    let res = test::call_service(&app, request("/").to_request()).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    handle.modify(|config| config.allowlist(["10.0.0.1"]));
+    for _ in 0..3 {
+        let res = test::call_service(&app, request("/").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+    let res = test::call_service(&app, request_from("/", "10.0.0.2").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = test::call_service(&app, request_from("/", "10.0.0.2").to_request()).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    handle.modify(|config| config.route_limit("/export", 1, Duration::from_secs(60)));
+    let res = test::call_service(&app, request_from("/export", "10.0.0.2").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn sub_second_windows_start_over() {
+    let config = RateLimitConfig::default()
+        .max_requests(1)
+        .window(Duration::from_millis(100));
+    let app = test::init_service(
+        App::new()
+            .wrap(RateLimit::new(config, MemoryStore::new()))
+            .route("/", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+
+    test::call_service(&app, request("/").to_request()).await;
+    let res = test::call_service(&app, request("/").to_request()).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    actix_web::rt::time::sleep(Duration::from_millis(120)).await;
+    let res = test::call_service(&app, request("/").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn rate_strings_limit_and_delay() {
+    let strict = RateLimitConfig::from_rate("1/200ms").unwrap();
+    let burst = RateLimitConfig::from_rate("1/200ms burst=1").unwrap();
+    let app = test::init_service(
+        App::new()
+            .service(
+                web::scope("/strict")
+                    .wrap(RateLimit::new(strict, MemoryStore::new()))
+                    .route("", web::get().to(HttpResponse::Ok)),
+            )
+            .service(
+                web::scope("/burst")
+                    .wrap(RateLimit::new(burst, MemoryStore::new()))
+                    .route("", web::get().to(HttpResponse::Ok)),
+            ),
+    )
+    .await;
+
+    test::call_service(&app, request("/strict").to_request()).await;
+    let res = test::call_service(&app, request("/strict").to_request()).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // The request over the rate waits for the quota instead
+    test::call_service(&app, request("/burst").to_request()).await;
+    let started = Instant::now();
+    let res = test::call_service(&app, request("/burst").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(started.elapsed() >= Duration::from_millis(100));
+}
+
+#[actix_web::test]
+async fn store_errors_fail_open_unless_configured() {
+    let app = test::init_service(
+        App::new()
+            .service(
+                web::scope("/open")
+                    .wrap(RateLimit::new(RateLimitConfig::default(), Unreachable))
+                    .route("", web::get().to(HttpResponse::Ok)),
+            )
+            .service(
+                web::scope("/closed")
+                    .wrap(RateLimit::new(
+                        RateLimitConfig::default().fail_open(false),
+                        Unreachable,
+                    ))
+                    .route("", web::get().to(HttpResponse::Ok)),
+            ),
+    )
+    .await;
+
+    let res = test::call_service(&app, request("/open").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = test::call_service(&app, request("/closed").to_request()).await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[cfg(feature = "metrics")]
+#[actix_web::test]
+async fn store_latency_is_labelled_by_route() {
+    use metrics::{
+        Counter, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit,
+    };
+    use std::sync::Mutex;
+
+    /// Recorder keeping the labels of the histograms registered.
+    #[derive(Default)]
+    struct Histograms(Mutex<Vec<Vec<(String, String)>>>);
+
+    impl Recorder for Histograms {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn register_counter(&self, _: &Key, _: &Metadata<'_>) -> Counter {
+            Counter::noop()
+        }
+
+        fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::noop()
+        }
+
+        fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+            if key.name() == "ratelimit_store_duration_seconds" {
+                let labels = key
+                    .labels()
+                    .map(|label| (label.key().to_string(), label.value().to_string()))
+                    .collect();
+                self.0.lock().unwrap().push(labels);
+            }
+            Histogram::noop()
+        }
+    }
+
+    let recorder = Histograms::default();
+    let guard = metrics::set_default_local_recorder(&recorder);
+    let app = test::init_service(
+        App::new()
+            .wrap(RateLimit::new(
+                RateLimitConfig::default(),
+                MemoryStore::new(),
+            ))
+            .route("/users/{id}", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+    test::call_service(&app, request("/users/42").to_request()).await;
+    test::call_service(&app, request("/missing").to_request()).await;
+    drop(guard);
+
+    let route = |labels: &Vec<(String, String)>| {
+        labels
+            .iter()
+            .find(|(key, _)| key == "route")
+            .map(|(_, value)| value.clone())
+    };
+    let histograms = recorder.0.into_inner().unwrap();
+    let routes: Vec<_> = histograms.iter().map(route).collect();
+    assert_eq!(
+        routes,
+        [
+            Some("/users/{id}".to_string()),
+            Some("unmatched".to_string())
+        ]
+    );
+}